@@ -1,30 +1,91 @@
+use super::cepstrum::{CepstrumFrame, CepstrumView};
 use super::common::*;
-use super::spectrograph::Spectrograph;
+use super::generator::{ChannelParams, GeneratorView};
+use super::goertzel::GoertzelView;
+use super::goniometer::GonioView;
+use super::hilbert::HilbertView;
+use super::hpss::{HpssMode, HpssView};
+use super::imd::{ImdResult, ImdView};
+use super::impulse::{ImpulseResponse, ImpulseView, SweepParams};
+use super::lpc::{LpcFrame, LpcView};
+use super::octave::RtaView;
+use super::patchbay::{PatchbayView, PortGraph};
+use super::phase::PhaseView;
+use super::player::PlayerView;
+use super::scope::ScopeView;
+use super::spectrograph::{self, Spectrograph};
 use super::spectrum::Spectrum;
 use anyhow::Result;
+use std::collections::VecDeque;
+use std::sync::atomic::Ordering;
 use std::sync::{Arc, RwLock};
 use std::thread;
 
+// Raw sample blocks kept for the UI when it falls behind
+const MAX_SAMPLE_BLOCKS: usize = 64;
+
+// Sends queued commands to a Jack callback until its bounded queue is full, the rest
+// are tried again on the next frame.
+fn flush<T>(queue: &mut VecDeque<T>, tx: &crossbeam_channel::Sender<T>) {
+    while let Some(command) = queue.pop_front() {
+        match tx.try_send(command) {
+            Ok(()) => {}
+            Err(crossbeam_channel::TrySendError::Full(command)) => {
+                queue.push_front(command);
+                return;
+            }
+            // the client is gone, nothing will take them
+            Err(crossbeam_channel::TrySendError::Disconnected(_)) => queue.clear(),
+        }
+    }
+}
+
 pub struct State {
-    data: Vec<f32>,
-    fft_size: usize,
+    frames: VecDeque<Frame>,
+    // total number of frames received, frames only keeps the last MAX_DATA_LENGTH
+    count: usize,
+    impulse: Option<ImpulseResponse>,
+    spectra: Option<ComplexFrame>,
+    samples: VecDeque<SampleBlock>,
+    cepstrum: Option<CepstrumFrame>,
+    lpc: Option<LpcFrame>,
+    imd: Option<ImdResult>,
+    ports: Option<PortGraph>,
+    sample_rate: Option<usize>,
 }
 
 impl State {
     pub fn new() -> State {
         State {
-            data: Vec::with_capacity(MAX_DATA_LENGTH * FFT_MAX_BUFF_SIZE),
-            fft_size: FFT_MAX_BUFF_SIZE,
+            frames: VecDeque::with_capacity(MAX_DATA_LENGTH),
+            count: 0,
+            impulse: None,
+            spectra: None,
+            samples: VecDeque::with_capacity(MAX_SAMPLE_BLOCKS),
+            cepstrum: None,
+            lpc: None,
+            imd: None,
+            ports: None,
+            sample_rate: None,
         }
     }
 
-    pub fn head_vec(&self) -> Vec<f32> {
-        if (self.data.len() < self.fft_size) {
-            return vec![];
+    pub fn push_frame(&mut self, frame: Frame) {
+        if self.frames.len() == MAX_DATA_LENGTH {
+            self.frames.pop_front();
         }
-        let start = self.data.len() - self.fft_size;
-        let slice = &self.data[start..];
-        return slice.to_vec();
+        self.frames.push_back(frame);
+        self.count += 1;
+    }
+
+    // Frames that arrived after the first `count` frames.
+    pub fn frames_since(&self, count: usize) -> Vec<Frame> {
+        let new = (self.count - count).min(self.frames.len());
+        self.frames
+            .iter()
+            .skip(self.frames.len() - new)
+            .cloned()
+            .collect()
     }
 }
 
@@ -33,22 +94,72 @@ pub struct App {
     pub label: String,
     pub value: f32,
     pub plot: Spectrum,
+    pub rta: RtaView,
+    pub lpc: LpcView,
     pub spectrograph: Spectrograph,
+    pub hpss: HpssView,
+    pub harmonic: Spectrograph,
+    pub percussive: Spectrograph,
+    // latest frame after weighting and averaging
+    pub shown: Option<Frame>,
+    pub impulse: ImpulseView,
+    pub phase: PhaseView,
+    pub scope: ScopeView,
+    pub gonio: GonioView,
+    pub cepstrum: CepstrumView,
+    pub tones: GoertzelView,
+    pub generator: GeneratorView,
+    pub player: PlayerView,
+    pub hilbert: HilbertView,
+    pub imd: ImdView,
+    pub patchbay: PatchbayView,
+    // whether the analyzer was last asked to forward raw samples
+    pub raw_wanted: bool,
     pub last_head: usize,
+    pub sample_rate: usize,
+    // input channels of the analyzer
+    pub channels: usize,
+    pub gen_tx: crossbeam_channel::Sender<GenCommand>,
+    // generator settings changed from MIDI
+    pub midi_rx: crossbeam_channel::Receiver<(usize, ChannelParams)>,
+    pub player_tx: crossbeam_channel::Sender<PlayerCommand>,
+    // commands that did not fit in the generator's and player's queues yet
+    pub gen_queue: VecDeque<GenCommand>,
+    pub player_queue: VecDeque<PlayerCommand>,
+    // a capture to start once its sweep is on its way to the generator
+    pub pending_capture: Option<SweepParams>,
+    pub patch_tx: crossbeam_channel::Sender<PatchCommand>,
+    pub analyzer_tx: crossbeam_channel::Sender<AnalyzerCommand>,
+    pub status: Arc<JackStatus>,
 }
 
 impl App {
     pub fn listen(
         &self,
         state: Arc<RwLock<State>>,
-        rx: crossbeam_channel::Receiver<Vec<f32>>,
+        rx: crossbeam_channel::Receiver<Message>,
         repaint_signal: Arc<GliumRepaintSignal>,
     ) {
         thread::spawn(move || loop {
-            if let Ok(mut data) = rx.recv() {
+            if let Ok(msg) = rx.recv() {
                 {
                     let mut lock = state.write().expect("mutex is poisoned");
-                    lock.data.append(&mut data);
+                    match msg {
+                        Message::Tick(frame) => lock.push_frame(frame),
+                        Message::Impulse(ir) => lock.impulse = Some(ir),
+                        Message::Spectra(spectra) => lock.spectra = Some(spectra),
+                        Message::Samples(block) => {
+                            if lock.samples.len() == MAX_SAMPLE_BLOCKS {
+                                lock.samples.pop_front();
+                            }
+                            lock.samples.push_back(block);
+                        }
+                        Message::Cepstrum(frame) => lock.cepstrum = Some(frame),
+                        Message::Lpc(frame) => lock.lpc = Some(frame),
+                        Message::Imd(result) => lock.imd = Some(result),
+                        Message::Ports(graph) => lock.ports = Some(graph),
+                        Message::SampleRate(sr) => lock.sample_rate = Some(sr),
+                    }
                 }
                 repaint_signal.request_repaint();
             }
@@ -60,42 +171,301 @@ impl App {
             label,
             value,
             plot,
+            rta,
+            lpc,
             spectrograph,
+            hpss,
+            harmonic,
+            percussive,
+            shown,
+            impulse,
+            phase,
+            scope,
+            gonio,
+            cepstrum,
+            tones,
+            generator,
+            player,
+            hilbert,
+            imd,
+            patchbay,
+            raw_wanted,
+            sample_rate,
+            channels,
+            gen_tx,
+            midi_rx,
+            player_tx,
+            gen_queue,
+            player_queue,
+            pending_capture,
+            patch_tx,
+            analyzer_tx,
+            status,
             ..
         } = self;
 
-        let (data, current_head) = {
-            let lock = state.read().expect("mutex poisoned");
-            (lock.head_vec(), lock.data.len())
+        let (
+            frames,
+            current_head,
+            new_impulse,
+            new_spectra,
+            blocks,
+            new_cepstrum,
+            new_lpc,
+            new_imd,
+            new_ports,
+            new_sample_rate,
+        ) = {
+            let mut lock = state.write().expect("mutex poisoned");
+            (
+                lock.frames_since(self.last_head),
+                lock.count,
+                lock.impulse.take(),
+                lock.spectra.take(),
+                std::mem::take(&mut lock.samples),
+                lock.cepstrum.take(),
+                lock.lpc.take(),
+                lock.imd.take(),
+                lock.ports.take(),
+                lock.sample_rate.take(),
+            )
         };
+        if let Some(sr) = new_sample_rate {
+            *sample_rate = sr;
+        }
+        while let Ok((channel, params)) = midi_rx.try_recv() {
+            generator.set_channel(channel, params);
+        }
+        if let Some(ir) = new_impulse {
+            impulse.set_response(ir);
+        }
+        if let Some(spectra) = new_spectra {
+            phase.set_spectra(spectra);
+        }
+        if let Some(frame) = new_cepstrum {
+            cepstrum.set_frame(frame);
+        }
+        if let Some(frame) = new_lpc {
+            lpc.set_frame(frame);
+        }
+        if let Some(result) = new_imd {
+            imd.set_result(result);
+        }
+        if let Some(graph) = new_ports {
+            patchbay.set_graph(graph);
+        }
+        for block in blocks.iter() {
+            if scope.open {
+                scope.push(block, *sample_rate);
+            }
+            if gonio.open {
+                gonio.push(&block.channels, *sample_rate);
+            }
+            if tones.open {
+                tones.push(&block.channels, *sample_rate);
+            }
+            if hilbert.open {
+                hilbert.push(&block.channels, *sample_rate);
+            }
+        }
+
+        let nyquist = *sample_rate as f32 / 2.0;
+        for frame in frames {
+            let frame = plot.process(frame);
+            let (min_hz, max_hz) = frame.range();
+            let marks = lpc.marks(min_hz, max_hz, frame.axis.is_log());
+            spectrograph.update(plot.spectrograph_row(&frame, FFT_MAX_BUFF_SIZE), &marks);
+            if let Some((h, p)) = hpss.push(&frame) {
+                harmonic.update(plot.spectrograph_row(&h, FFT_MAX_BUFF_SIZE), &[]);
+                percussive.update(plot.spectrograph_row(&p, FFT_MAX_BUFF_SIZE), &[]);
+            }
+            *shown = Some(frame);
+        }
 
         let app_rect = ctx.available_rect();
 
+        egui::SidePanel::left("controls").show(ctx, |ui| {
+            ui.heading(label.as_str());
+            ui.label(format!(
+                "{} Hz, {} frames, {} xruns",
+                sample_rate,
+                status.buffer_size.load(Ordering::Relaxed),
+                status.xruns.load(Ordering::Relaxed)
+            ));
+            ui.collapsing("Connections", |ui| {
+                if let Some(command) = patchbay.controls_ui(ui) {
+                    patch_tx.send(command).ok();
+                }
+            });
+            ui.collapsing("Generator", |ui| {
+                gen_queue.extend(generator.controls_ui(ui, *sample_rate));
+            });
+            ui.collapsing("File player", |ui| {
+                player_queue.extend(player.controls_ui(ui, *sample_rate));
+            });
+            ui.collapsing("Analyzer", |ui| {
+                if let Some(mode) = plot.options_ui(ui, *sample_rate) {
+                    analyzer_tx.send(AnalyzerCommand::SetMode(mode)).ok();
+                }
+                rta.options_ui(ui);
+            });
+            ui.collapsing("Harmonic/percussive", |ui| hpss.controls_ui(ui));
+            ui.collapsing("Linear prediction", |ui| {
+                if let Some(params) = lpc.controls_ui(ui, *sample_rate) {
+                    analyzer_tx.send(AnalyzerCommand::Lpc(params)).ok();
+                }
+            });
+            ui.collapsing("Impulse response", |ui| {
+                if let Some((params, sweep)) = impulse.controls_ui(ui, *sample_rate) {
+                    gen_queue.push_back(GenCommand::Sweep(sweep, params.gain()));
+                    *pending_capture = Some(params);
+                }
+            });
+            ui.collapsing("Phase", |ui| {
+                if let Some(on) = phase.controls_ui(ui) {
+                    analyzer_tx.send(AnalyzerCommand::ComplexSpectra(on)).ok();
+                }
+            });
+            ui.collapsing("Oscilloscope", |ui| scope.controls_ui(ui, *channels));
+            ui.collapsing("Goniometer", |ui| gonio.controls_ui(ui, *channels));
+            ui.collapsing("Cepstrum", |ui| {
+                if let Some(on) = cepstrum.controls_ui(ui) {
+                    analyzer_tx.send(AnalyzerCommand::Cepstrum(on)).ok();
+                }
+            });
+            ui.collapsing("Tone detectors", |ui| tones.controls_ui(ui, *channels));
+            ui.collapsing("Intermodulation", |ui| {
+                if let Some(standard) = imd.controls_ui(ui) {
+                    analyzer_tx.send(AnalyzerCommand::Imd(standard)).ok();
+                }
+            });
+            ui.collapsing("Instantaneous amplitude/frequency", |ui| {
+                hilbert.controls_ui(ui, *channels)
+            });
+        });
+
+        let wanted = scope.open || gonio.open || tones.open || hilbert.open;
+        if wanted != *raw_wanted {
+            analyzer_tx.send(AnalyzerCommand::RawSamples(wanted)).ok();
+            *raw_wanted = wanted;
+        }
+
+        flush(gen_queue, gen_tx);
+        flush(player_queue, player_tx);
+        // Capturing before the sweep is queued could record nothing but silence
+        let sweep_queued = gen_queue.iter().any(|c| matches!(c, GenCommand::Sweep(..)));
+        if !sweep_queued {
+            if let Some(params) = pending_capture.take() {
+                analyzer_tx.send(AnalyzerCommand::Capture(params)).ok();
+            }
+        }
+        if !gen_queue.is_empty() || !player_queue.is_empty() {
+            ctx.request_repaint();
+        }
+
+        // Extra views live in their own panel, the spectrograph is drawn over the
+        // central panel after egui and would hide any window above it.
+        if impulse.open
+            || phase.open
+            || scope.open
+            || gonio.open
+            || cepstrum.open
+            || tones.open
+            || imd.open
+            || patchbay.open
+        {
+            egui::SidePanel::right("views").show(ctx, |ui| {
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    if impulse.open {
+                        ui.collapsing("Impulse response", |ui| impulse.ui(ui));
+                    }
+                    if phase.open {
+                        ui.collapsing("Phase", |ui| phase.ui(ui));
+                    }
+                    if scope.open {
+                        ui.collapsing("Oscilloscope", |ui| scope.ui(ui));
+                    }
+                    if gonio.open {
+                        ui.collapsing("Goniometer", |ui| gonio.ui(ui));
+                    }
+                    if cepstrum.open {
+                        ui.collapsing("Cepstrum", |ui| cepstrum.ui(ui));
+                    }
+                    if tones.open {
+                        ui.collapsing("Tone detectors", |ui| tones.ui(ui));
+                    }
+                    if imd.open {
+                        ui.collapsing("Intermodulation", |ui| imd.ui(ui));
+                    }
+                    if patchbay.open {
+                        ui.collapsing("Connections", |ui| {
+                            for command in patchbay.ui(ui) {
+                                patch_tx.send(command).ok();
+                            }
+                        });
+                    }
+                });
+            });
+        }
+
         egui::CentralPanel::default().show(ctx, |ui| {
             egui::Frame::dark_canvas(ui.style()).show(ui, |ui| {
                 let avail_size = ui.available_size();
 
                 let plot_height = avail_size.y * 0.3;
-                let spec_height = avail_size.y * 0.7;
+                let hilbert_height = if hilbert.open {
+                    avail_size.y * 0.25
+                } else {
+                    0.0
+                };
+                let spec_height = avail_size.y * 0.7 - hilbert_height;
                 let (_, place_rect) = ui.allocate_space(egui::Vec2 {
                     x: avail_size.x,
-                    y: spec_height,
+                    y: spec_height - spectrograph::AXIS_HEIGHT,
                 });
-                plot.ui(ui, plot_height, &data);
+                let ((min_hz, max_hz), log) = shown
+                    .as_ref()
+                    .map_or(((0.0, nyquist), false), |f| (f.range(), f.axis.is_log()));
+                spectrograph::axis_ui(ui, min_hz, max_hz, log);
+                if hilbert.open {
+                    hilbert.ui(ui, hilbert_height);
+                }
+                if rta.enabled {
+                    rta.ui(ui, plot_height, shown.as_ref(), nyquist);
+                } else {
+                    plot.ui(ui, plot_height, shown.as_ref(), lpc.envelope());
+                }
 
-                spectrograph.set_vertex_position(place_rect, app_rect);
+                match hpss.mode {
+                    HpssMode::Off => spectrograph.set_vertex_position(place_rect, app_rect),
+                    HpssMode::Harmonic => harmonic.set_vertex_position(place_rect, app_rect),
+                    HpssMode::Percussive => percussive.set_vertex_position(place_rect, app_rect),
+                    HpssMode::Both => {
+                        let middle = place_rect.center().y;
+                        let mut top = place_rect;
+                        let mut bottom = place_rect;
+                        top.max.y = middle;
+                        bottom.min.y = middle;
+                        harmonic.set_vertex_position(top, app_rect);
+                        percussive.set_vertex_position(bottom, app_rect);
+                    }
+                }
             });
         });
 
-        if self.last_head != current_head {
-            spectrograph.update(data);
-        }
-
         self.last_head = current_head;
     }
 
     pub fn draw(&mut self, target: &mut glium::Frame) {
         // draw things on top of egui here
-        self.spectrograph.draw(target);
+        match self.hpss.mode {
+            HpssMode::Off => self.spectrograph.draw(target),
+            HpssMode::Harmonic => self.harmonic.draw(target),
+            HpssMode::Percussive => self.percussive.draw(target),
+            HpssMode::Both => {
+                self.harmonic.draw(target);
+                self.percussive.draw(target);
+            }
+        }
     }
 }
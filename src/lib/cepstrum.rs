@@ -0,0 +1,204 @@
+use egui::widgets::plot::{Curve, Plot, Value};
+use num_complex::Complex32;
+use realfft::{ComplexToReal, RealFftPlanner, RealToComplex};
+use std::sync::Arc;
+
+const PEAKS: usize = 5;
+
+#[derive(Debug, Clone)]
+pub struct CepstrumFrame {
+    // real cepstrum, index is quefrency in samples
+    pub cepstrum: Vec<f32>,
+    // autocorrelation normalized to 1 at lag 0, index is lag in samples
+    pub autocorrelation: Vec<f32>,
+    pub sample_rate: usize,
+}
+
+// Cepstrum and autocorrelation of analysis frames, both computed through the FFT.
+pub struct Cepstral {
+    size: usize,
+    sample_rate: usize,
+    fwd: Arc<dyn RealToComplex<f32>>,
+    inv: Arc<dyn ComplexToReal<f32>>,
+    fwd_padded: Arc<dyn RealToComplex<f32>>,
+    inv_padded: Arc<dyn ComplexToReal<f32>>,
+    buf: Vec<f32>,
+    spec: Vec<Complex32>,
+    padded: Vec<f32>,
+    padded_spec: Vec<Complex32>,
+}
+
+impl Cepstral {
+    pub fn new(planner: &mut RealFftPlanner<f32>, size: usize, sample_rate: usize) -> Cepstral {
+        let fwd = planner.plan_fft_forward(size);
+        let inv = planner.plan_fft_inverse(size);
+        // the autocorrelation is zero padded to twice the length so it does not wrap
+        let fwd_padded = planner.plan_fft_forward(size * 2);
+        let inv_padded = planner.plan_fft_inverse(size * 2);
+        Cepstral {
+            size,
+            sample_rate,
+            buf: fwd.make_input_vec(),
+            spec: fwd.make_output_vec(),
+            padded: fwd_padded.make_input_vec(),
+            padded_spec: fwd_padded.make_output_vec(),
+            fwd,
+            inv,
+            fwd_padded,
+            inv_padded,
+        }
+    }
+
+    pub fn process(&mut self, signal: &[f32], window: &[f32]) -> CepstrumFrame {
+        let n = self.size;
+
+        // real cepstrum: inverse transform of the log magnitude of the windowed frame
+        for i in 0..n {
+            self.buf[i] = signal[i] * window[i];
+        }
+        self.fwd.process(&mut self.buf, &mut self.spec).unwrap();
+        for x in self.spec.iter_mut() {
+            *x = Complex32::new(x.norm().max(1e-10).ln(), 0.0);
+        }
+        self.inv.process(&mut self.spec, &mut self.buf).unwrap();
+        let cepstrum = self.buf[0..n / 2].iter().map(|c| c / n as f32).collect();
+
+        // autocorrelation: inverse transform of the power spectrum
+        self.padded[0..n].copy_from_slice(&signal[0..n]);
+        self.padded[n..].iter_mut().for_each(|x| *x = 0.0);
+        self.fwd_padded
+            .process(&mut self.padded, &mut self.padded_spec)
+            .unwrap();
+        for x in self.padded_spec.iter_mut() {
+            *x = Complex32::new(x.norm_sqr(), 0.0);
+        }
+        self.inv_padded
+            .process(&mut self.padded_spec, &mut self.padded)
+            .unwrap();
+        let zero_lag = self.padded[0].max(f32::MIN_POSITIVE);
+        let autocorrelation = self.padded[0..n / 2].iter().map(|r| r / zero_lag).collect();
+
+        CepstrumFrame {
+            cepstrum,
+            autocorrelation,
+            sample_rate: self.sample_rate,
+        }
+    }
+}
+
+// Indices of the count largest local maxima of data[from..to], largest first.
+pub fn pick_peaks(data: &[f32], from: usize, to: usize, count: usize) -> Vec<usize> {
+    let to = to.min(data.len().saturating_sub(1));
+    let mut peaks: Vec<usize> = (from.max(1)..to)
+        .filter(|i| data[*i] > data[i - 1] && data[*i] >= data[i + 1])
+        .collect();
+    peaks.sort_by(|a, b| data[*b].partial_cmp(&data[*a]).unwrap());
+    peaks.truncate(count);
+    peaks
+}
+
+pub struct CepstrumView {
+    pub open: bool,
+    // quefrencies and lags shorter than this are ignored when picking peaks
+    min_ms: f32,
+    max_ms: f32,
+    smoothing: f32,
+    frame: Option<CepstrumFrame>,
+}
+
+impl Default for CepstrumView {
+    fn default() -> Self {
+        Self {
+            open: false,
+            min_ms: 1.0,
+            max_ms: 50.0,
+            smoothing: 1.0,
+            frame: None,
+        }
+    }
+}
+
+impl CepstrumView {
+    pub fn set_frame(&mut self, frame: CepstrumFrame) {
+        let a = self.smoothing;
+        self.frame = Some(match self.frame.take() {
+            Some(prev) if prev.cepstrum.len() == frame.cepstrum.len() => {
+                let blend = |p: &[f32], n: &[f32]| -> Vec<f32> {
+                    p.iter()
+                        .zip(n.iter())
+                        .map(|(p, n)| p + a * (n - p))
+                        .collect()
+                };
+                CepstrumFrame {
+                    cepstrum: blend(&prev.cepstrum, &frame.cepstrum),
+                    autocorrelation: blend(&prev.autocorrelation, &frame.autocorrelation),
+                    sample_rate: frame.sample_rate,
+                }
+            }
+            _ => frame,
+        });
+    }
+
+    // Returns whether the analysis is wanted, whenever that changes.
+    pub fn controls_ui(&mut self, ui: &mut egui::Ui) -> Option<bool> {
+        let was_open = self.open;
+        ui.checkbox(&mut self.open, "show");
+        ui.add(egui::Slider::new(&mut self.min_ms, 0.0..=20.0).text("min ms"));
+        ui.add(egui::Slider::new(&mut self.max_ms, 5.0..=85.0).text("max ms"));
+        ui.add(egui::Slider::new(&mut self.smoothing, 0.01..=1.0).text("smoothing"));
+        if self.open != was_open {
+            Some(self.open)
+        } else {
+            None
+        }
+    }
+
+    fn trace_ui(&self, ui: &mut egui::Ui, id: &str, data: &[f32], sample_rate: usize) {
+        let ms = 1000.0 / sample_rate as f32;
+        let from = (self.min_ms / ms) as usize;
+        let to = ((self.max_ms / ms) as usize).min(data.len());
+
+        let values = data[..to]
+            .iter()
+            .enumerate()
+            .map(|(i, v)| Value::new((i as f32 * ms) as f64, *v as f64))
+            .collect();
+        ui.add(
+            Plot::new(id)
+                .curve(
+                    Curve::from_values(values)
+                        .color(egui::Color32::from_rgb(200, 100, 100))
+                        .name("ms"),
+                )
+                .height(140.0),
+        );
+
+        let peaks: Vec<String> = pick_peaks(data, from, to, PEAKS)
+            .iter()
+            .map(|i| {
+                let t = *i as f32 * ms;
+                format!("{:.2} ms ({:.1} Hz)", t, 1000.0 / t)
+            })
+            .collect();
+        ui.label(format!("peaks: {}", peaks.join(", ")));
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        let frame = match &self.frame {
+            Some(f) => f,
+            None => {
+                ui.label("waiting for frames");
+                return;
+            }
+        };
+        ui.label("cepstrum");
+        self.trace_ui(ui, "cepstrum", &frame.cepstrum, frame.sample_rate);
+        ui.label("autocorrelation");
+        self.trace_ui(
+            ui,
+            "autocorrelation",
+            &frame.autocorrelation,
+            frame.sample_rate,
+        );
+    }
+}
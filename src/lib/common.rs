@@ -1,4 +1,18 @@
+use super::cepstrum::CepstrumFrame;
+use super::cwt::CwtParams;
+use super::generator::ChannelParams;
+use super::imd::{ImdResult, ImdStandard};
+use super::impulse::{ImpulseResponse, SweepParams};
+use super::lpc::{LpcFrame, LpcParams};
+use super::multires::MultiResParams;
+use super::patchbay::PortGraph;
+use super::player::AudioFile;
+use super::psd::{MultitaperParams, WelchParams};
+use super::zoom::ZoomParams;
 use glium::glutin;
+use num_complex::Complex32;
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
 
 pub const AUDIO_BUFF_SIZE: usize = 8192;
 pub const FFT_MAX_SIZE: usize = 8192;
@@ -6,10 +20,246 @@ pub const FFT_MAX_BUFF_SIZE: usize = 4097;
 pub const MAX_DATA_LENGTH: usize = 10000;
 pub const APP_WIDTH: f32 = 1200.0;
 pub const APP_HEIGHT: f32 = 800.0;
+// Input and output channels of the Jack clients, chosen at startup
+pub const DEFAULT_CHANNELS: usize = 2;
+pub const MAX_CHANNELS: usize = 32;
+pub const DEFAULT_SAMPLE_RATE: usize = 48000;
+// Generator commands that can be queued between two Jack callbacks
+pub const GEN_COMMANDS: usize = 64;
+// Value of a SyncMarker before the generator has started a burst
+pub const NO_SYNC: u64 = u64::MAX;
+
+// Jack frame time of the latest burst start, written by the generator and read by
+// the analyzer to find the burst in its input. Frame times wrap around at 2^32.
+pub type SyncMarker = Arc<AtomicU64>;
+
+// Position of the file player in frames of the file, written by the player.
+pub type Playhead = Arc<AtomicU64>;
+
+// What the Jack clients have seen of the server, for the UI.
+#[derive(Debug, Default)]
+pub struct JackStatus {
+    pub xruns: AtomicU64,
+    // frames in the analyzer's latest process cycle
+    pub buffer_size: AtomicU64,
+    pub sample_rate: AtomicU64,
+    // period of the latest xrun counted, clients are all told about the same one
+    pub last_xrun: AtomicU64,
+}
+
+// Frequency in Hz of each bin of a frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FreqAxis {
+    // Bins step Hz apart, as out of an FFT
+    Linear { start: f32, step: f32 },
+    // Each bin ratio times the frequency of the previous one, as in a scalogram
+    Log { start: f32, ratio: f32 },
+}
+
+impl FreqAxis {
+    pub fn hz(&self, bin: usize) -> f32 {
+        self.hz_at(bin as f32)
+    }
+
+    fn hz_at(&self, pos: f32) -> f32 {
+        match *self {
+            FreqAxis::Linear { start, step } => start + step * pos,
+            FreqAxis::Log { start, ratio } => start * ratio.powf(pos),
+        }
+    }
+
+    // Fractional bin index of a frequency.
+    pub fn bin(&self, hz: f32) -> f32 {
+        match *self {
+            FreqAxis::Linear { start, step } => (hz - start) / step,
+            FreqAxis::Log { start, ratio } => (hz / start).ln() / ratio.ln(),
+        }
+    }
+
+    // Lower and upper edge of a bin, halfway to its neighbours on the axis.
+    pub fn edges(&self, bin: usize) -> (f32, f32) {
+        (self.hz_at(bin as f32 - 0.5), self.hz_at(bin as f32 + 0.5))
+    }
+
+    pub fn width(&self, bin: usize) -> f32 {
+        let (lo, hi) = self.edges(bin);
+        hi - lo
+    }
+
+    pub fn is_log(&self) -> bool {
+        matches!(self, FreqAxis::Log { .. })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scale {
+    // Linear amplitude, normalized by the spectrum length
+    Amplitude,
+    // Power spectral density in FS²/Hz
+    Density,
+}
+
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub bins: Vec<f32>,
+    pub axis: FreqAxis,
+    pub scale: Scale,
+    // Seconds between this frame and the previous one
+    pub period: f32,
+}
+
+impl Frame {
+    // Power in FS² that falls into a bin.
+    pub fn bin_power(&self, bin: usize) -> f32 {
+        match self.scale {
+            Scale::Amplitude => self.bins[bin] * self.bins[bin],
+            Scale::Density => self.bins[bin] * self.axis.width(bin),
+        }
+    }
+
+    // Frequencies of the first and last bin.
+    pub fn range(&self) -> (f32, f32) {
+        (self.axis.hz(0), self.axis.hz(self.bins.len() - 1))
+    }
+
+    // Samples the frame at len frequencies from min_hz to max_hz, linearly
+    // interpolating between bins. The frequencies are evenly spaced on the frame's
+    // own axis, so a log axis stays logarithmic. Frequencies outside the frame read
+    // as 0.
+    pub fn resample(&self, len: usize, min_hz: f32, max_hz: f32) -> Vec<f32> {
+        let last = self.bins.len() - 1;
+        (0..len)
+            .map(|i| {
+                let t = i as f32 / (len - 1) as f32;
+                let hz = if self.axis.is_log() {
+                    min_hz * (max_hz / min_hz).powf(t)
+                } else {
+                    min_hz + (max_hz - min_hz) * t
+                };
+                let pos = self.axis.bin(hz);
+                if pos < 0.0 || pos > last as f32 {
+                    return 0.0;
+                }
+                let lo = pos.floor() as usize;
+                let hi = (lo + 1).min(last);
+                let t = pos - lo as f32;
+                self.bins[lo] * (1.0 - t) + self.bins[hi] * t
+            })
+            .collect()
+    }
+}
+
+// A block of raw input samples.
+#[derive(Debug, Clone)]
+pub struct SampleBlock {
+    // one vec per channel
+    pub channels: Vec<Vec<f32>>,
+    // where in the block the latest generator burst started
+    pub sync: Option<usize>,
+}
+
+// Complex spectrum of every input channel, with the phase intact.
+#[derive(Debug, Clone)]
+pub struct ComplexFrame {
+    pub channels: Vec<Vec<Complex32>>,
+    pub axis: FreqAxis,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AnalysisMode {
+    Spectrum,
+    Psd(WelchParams),
+    // Density from DPSS tapered periodograms of short segments
+    Multitaper(MultitaperParams),
+    // Narrow band around a center frequency at a fine resolution
+    Zoom(ZoomParams),
+    // Several FFT sizes stitched together, longer ones for lower bands
+    MultiRes(MultiResParams),
+    // Morlet wavelet scalogram on a log frequency axis
+    Cwt(CwtParams),
+}
 
 #[derive(Debug, Clone)]
 pub enum Message {
-    Tick(Vec<f32>),
+    Tick(Frame),
+    Impulse(ImpulseResponse),
+    Spectra(ComplexFrame),
+    Samples(SampleBlock),
+    Cepstrum(CepstrumFrame),
+    Lpc(LpcFrame),
+    Imd(ImdResult),
+    Ports(PortGraph),
+    // Jack changed its sample rate, the analysis has been set up for the new one
+    SampleRate(usize),
+}
+
+// Commands sent from the UI into the generator's process callback. Anything carried
+// here must be allocated before sending, the audio thread only swaps it in.
+#[derive(Debug, Clone)]
+pub enum GenCommand {
+    // A measurement sweep and the gain to play it at
+    Sweep(Arc<Vec<f32>>, f32),
+    // New settings for one output channel
+    Channel(usize, ChannelParams),
+    // Starts the sweep on one output channel over
+    RestartSweep(usize),
+}
+
+// Transport commands sent from the UI into the file player's process callback.
+#[derive(Debug, Clone)]
+pub enum PlayerCommand {
+    Load(Arc<AudioFile>),
+    Play,
+    Pause,
+    // Pauses and goes back to the start
+    Stop,
+    // Jumps to a frame of the file
+    Seek(usize),
+    Looping(bool),
+}
+
+// Commands sent from the UI into the patchbay thread. Connections go from an output
+// port to an input port, by full port name.
+#[derive(Debug, Clone)]
+pub enum PatchCommand {
+    Connect(String, String),
+    Disconnect(String, String),
+    // Sends the ports and connections as they are now
+    Refresh,
+}
+
+// Commands sent from the UI into the analysis thread.
+#[derive(Debug, Clone)]
+pub enum AnalyzerCommand {
+    Capture(SweepParams),
+    SetMode(AnalysisMode),
+    // Turns the per channel complex spectra on or off
+    ComplexSpectra(bool),
+    // Turns forwarding of the raw input samples on or off
+    RawSamples(bool),
+    // Turns the cepstrum and autocorrelation analysis on or off
+    Cepstrum(bool),
+    // Runs linear prediction on every block with these settings, or stops it
+    Lpc(Option<LpcParams>),
+    // Measures intermodulation distortion for a tone pair, or stops it
+    Imd(Option<ImdStandard>),
+}
+
+const CHANNEL_COLORS: [(u8, u8, u8); 8] = [
+    (200, 100, 100),
+    (100, 160, 220),
+    (120, 200, 120),
+    (220, 200, 100),
+    (180, 120, 220),
+    (100, 200, 200),
+    (230, 150, 80),
+    (200, 200, 200),
+];
+
+// Trace color of an input channel.
+pub fn channel_color(channel: usize) -> egui::Color32 {
+    let (r, g, b) = CHANNEL_COLORS[channel % CHANNEL_COLORS.len()];
+    egui::Color32::from_rgb(r, g, b)
 }
 
 pub struct RequestRepaintEvent;
@@ -22,3 +272,14 @@ impl GliumRepaintSignal {
         self.0.lock().unwrap().send_event(RequestRepaintEvent).ok();
     }
 }
+
+// Picks one of the input channels.
+pub fn channel_combo(ui: &mut egui::Ui, label: &str, value: &mut usize, channels: usize) {
+    egui::ComboBox::from_label(label)
+        .selected_text(format!("in {}", *value + 1))
+        .show_ui(ui, |ui| {
+            for c in 0..channels {
+                ui.selectable_value(value, c, format!("in {}", c + 1));
+            }
+        });
+}
@@ -1,11 +1,25 @@
+use super::cepstrum::Cepstral;
 use super::common::*;
+use super::cwt::Cwt;
+use super::generator::{ChannelParams, MidiVoice, Oscillator};
+use super::imd::{Imd, ImdStandard};
+use super::impulse::Capture;
+use super::lpc::{Lpc, LpcParams};
+use super::multires::MultiRes;
+use super::patchbay::{ConnectRule, Direction, PortGraph};
+use super::player::Player;
+use super::psd::{Multitaper, Welch};
+use super::zoom::ZoomFft;
 use anyhow::Result;
 use crossbeam_channel;
-use jack::{AsyncClient, AudioIn, AudioOut, Port, PortSpec};
+use jack::{AsyncClient, AudioIn, AudioOut, MidiIn, Port, PortFlags, PortSpec};
 use num_complex::Complex32;
 use realfft::RealFftPlanner;
 use ringbuf::RingBuffer;
+use std::collections::HashSet;
 use std::f32::consts::PI;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 // const DEFAULT_FREQ_SCALE: i64 = 1; // log10
@@ -18,8 +32,8 @@ use std::time::Duration;
 // const DEFAULT_RESPONSE_TIME: f64 = 0.025;
 // const DEFAULT_RESPONSE_TIME_INDEX: i32 = 0;
 
-// This is the audio_biffer we are transferring Jack frames into. It must be larger than
-// than the Jack frame. This is asserted.
+// Jack frames are interleaved through a buffer of AUDIO_BUFF_SIZE frames, larger Jack
+// buffers go through it in chunks.
 
 fn fft_used_buff_size(fft_size: usize) -> usize {
     return (fft_size / 2) + 1;
@@ -29,84 +43,216 @@ fn port_name(port_basename: &str, port_index: i64) -> String {
     return format!("{}_{}", port_basename, port_index);
 }
 
+// Opens a client with one port per channel, named port_basename_1 and up.
 fn make_client<T>(
     client_name: &str,
     port_basename: &str,
-    port_spec_1: T,
-    port_spec_2: T,
-) -> Result<(jack::Client, Port<T>, Port<T>)>
+    port_spec: T,
+    channels: usize,
+) -> Result<(jack::Client, Vec<Port<T>>)>
 where
-    T: PortSpec,
+    T: PortSpec + Clone,
 {
+    if channels == 0 || channels > MAX_CHANNELS {
+        bail!(
+            "{} channels requested, 1 to {} are supported",
+            channels,
+            MAX_CHANNELS
+        );
+    }
     let (client, _status) = jack::Client::new(client_name, jack::ClientOptions::NO_START_SERVER)?;
-    let port_1 = client.register_port(&port_name(port_basename, 1), port_spec_1)?;
-    let port_2 = client.register_port(&port_name(port_basename, 2), port_spec_2)?;
+    let ports = (1..=channels)
+        .map(|i| client.register_port(&port_name(port_basename, i as i64), port_spec.clone()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    return Ok((client, ports));
+}
+
+// Connects the ports of two clients pairwise, as far as the smaller one goes.
+fn connect_ports<A, B>(client: &jack::Client, from: &A, to: &B) -> Result<()>
+where
+    A: PortName,
+    B: PortName,
+{
+    for i in 1..=from.port_count().min(to.port_count()) as i64 {
+        client.connect_ports_by_name(&from.client_port_name(i), &to.client_port_name(i))?
+    }
+    Ok(())
+}
 
-    return Ok((client, port_1, port_2));
+// Counts xruns and passes on sample rate changes. Every client is told about the
+// same xrun, so it is counted once per period.
+struct Notifier {
+    status: Arc<JackStatus>,
 }
 
-pub struct SineGen<'a> {
+impl jack::NotificationHandler for Notifier {
+    fn sample_rate(&mut self, _: &jack::Client, srate: jack::Frames) -> jack::Control {
+        self.status
+            .sample_rate
+            .store(srate as u64, Ordering::Release);
+        jack::Control::Continue
+    }
+
+    fn xrun(&mut self, client: &jack::Client) -> jack::Control {
+        let period = client.frame_time() as u64 / client.buffer_size().max(1) as u64;
+        if self.status.last_xrun.swap(period, Ordering::AcqRel) != period {
+            self.status.xruns.fetch_add(1, Ordering::AcqRel);
+        }
+        jack::Control::Continue
+    }
+}
+
+pub struct SignalGen<'a> {
     pub name: &'a str,
     pub port_basename: &'a str,
     pub sample_rate: usize,
-    jack_client: AsyncClient<(), SineProcessor>,
+    pub channels: usize,
+    jack_client: AsyncClient<Notifier, GenProcessor>,
 }
 
-impl<'a> SineGen<'a> {
+impl<'a> SignalGen<'a> {
+    // One output per entry of channels.
     pub fn new(
         name: &'a str,
-        port_spec_1: AudioOut,
-        port_spec_2: AudioOut,
-        freq_1: f32,
-        freq_2: f32,
-    ) -> Result<SineGen<'a>> {
+        port_spec: AudioOut,
+        channels: &[ChannelParams],
+        commands: crossbeam_channel::Receiver<GenCommand>,
+        sync: SyncMarker,
+        midi_params: crossbeam_channel::Sender<(usize, ChannelParams)>,
+        status: Arc<JackStatus>,
+    ) -> Result<SignalGen<'a>> {
         let port_basename = "out";
-        let (client, port_1, port_2) = make_client(name, port_basename, port_spec_1, port_spec_2)?;
+        let (client, ports) = make_client(name, port_basename, port_spec, channels.len())?;
+        let midi = client.register_port("midi_in", MidiIn::default())?;
 
         let sample_rate = client.sample_rate();
-        let process = SineProcessor {
-            port_1,
-            port_2,
-            frame_t: 1.0 / sample_rate as f32,
-            freq_1,
-            freq_2,
-            time: 0.,
+        let oscillators = channels
+            .iter()
+            .enumerate()
+            .map(|(c, params)| Oscillator::new(*params, sample_rate, c as u32 + 1))
+            .collect();
+        let process = GenProcessor {
+            sample_rate,
+            ports,
+            midi,
+            oscillators,
+            voices: vec![MidiVoice::default(); channels.len()],
+            commands,
+            sweep: None,
+            sweep_pos: 0,
+            sweep_gain: 1.0,
+            sync,
+            midi_params,
         };
 
-        let jack_client = client.activate_async((), process)?;
+        let jack_client = client.activate_async(Notifier { status }, process)?;
 
-        Ok(SineGen {
+        Ok(SignalGen {
             name,
             port_basename,
             sample_rate,
+            channels: channels.len(),
             jack_client,
         })
     }
 }
 
-struct SineProcessor {
-    port_1: jack::Port<AudioOut>,
-    port_2: jack::Port<AudioOut>,
-    frame_t: f32,
-    freq_1: f32,
-    freq_2: f32,
-    time: f32,
+struct GenProcessor {
+    sample_rate: usize,
+    ports: Vec<jack::Port<AudioOut>>,
+    // MIDI channel n drives output n
+    midi: jack::Port<MidiIn>,
+    oscillators: Vec<Oscillator>,
+    voices: Vec<MidiVoice>,
+    commands: crossbeam_channel::Receiver<GenCommand>,
+    sweep: Option<Arc<Vec<f32>>>,
+    sweep_pos: usize,
+    sweep_gain: f32,
+    sync: SyncMarker,
+    // settings changed from MIDI, so the UI can follow them
+    midi_params: crossbeam_channel::Sender<(usize, ChannelParams)>,
 }
 
-impl jack::ProcessHandler for SineProcessor {
-    fn process(&mut self, _: &jack::Client, ps: &jack::ProcessScope) -> jack::Control {
-        // Get output buffer
-        let out1 = self.port_1.as_mut_slice(ps);
-        let out2 = self.port_2.as_mut_slice(ps);
-
-        for (a, b) in out1.iter_mut().zip(out2.iter_mut()) {
-            let x1 = self.freq_1 * self.time * 2.0 * PI;
-            let y1 = x1.sin();
-            let x2 = self.freq_2 * self.time * 2.0 * PI;
-            let y2 = x2.sin();
-            *a = y1 as f32;
-            *b = y2 as f32;
-            self.time += self.frame_t;
+impl jack::ProcessHandler for GenProcessor {
+    fn process(&mut self, client: &jack::Client, ps: &jack::ProcessScope) -> jack::Control {
+        let sample_rate = client.sample_rate();
+        if sample_rate != self.sample_rate {
+            self.sample_rate = sample_rate;
+            self.oscillators
+                .iter_mut()
+                .for_each(|o| o.set_sample_rate(sample_rate));
+        }
+        while let Ok(command) = self.commands.try_recv() {
+            match command {
+                GenCommand::Sweep(sweep, gain) => {
+                    self.sweep = Some(sweep);
+                    self.sweep_pos = 0;
+                    self.sweep_gain = gain;
+                }
+                GenCommand::Channel(c, params) => {
+                    if let Some(osc) = self.oscillators.get_mut(c) {
+                        osc.set_params(params);
+                    }
+                }
+                GenCommand::RestartSweep(c) => {
+                    if let Some(osc) = self.oscillators.get_mut(c) {
+                        osc.restart();
+                    }
+                }
+            }
+        }
+
+        // A measurement sweep replaces the signals on all outputs until it is done
+        let frames = ps.n_frames() as usize;
+        let swept = match &self.sweep {
+            Some(sweep) => (sweep.len() - self.sweep_pos).min(frames),
+            None => 0,
+        };
+        // latest sample of the buffer a burst started on, on any output
+        let mut burst_at = None;
+
+        for (c, port) in self.ports.iter_mut().enumerate() {
+            let out = port.as_mut_slice(ps);
+            let osc = &mut self.oscillators[c];
+            let voice = &mut self.voices[c];
+            let mut events = self.midi.iter(ps).peekable();
+
+            for (i, y) in out.iter_mut().enumerate() {
+                // MIDI takes effect on the sample it is timed at
+                while events.peek().map_or(false, |e| e.time as usize <= i) {
+                    let event = events.next().unwrap();
+                    if event.bytes.first().map(|s| (s & 0x0f) as usize) != Some(c) {
+                        continue;
+                    }
+                    let mut params = osc.params();
+                    if voice.handle(event.bytes, &mut params) {
+                        osc.set_params(params);
+                        self.midi_params.try_send((c, params)).ok();
+                    }
+                }
+
+                if i < swept {
+                    *y = self.sweep_gain * self.sweep.as_ref().unwrap()[self.sweep_pos + i];
+                    continue;
+                }
+                *y = osc.next();
+                if osc.burst_started() {
+                    burst_at = burst_at.max(Some(i));
+                }
+            }
+        }
+
+        if let Some(sweep) = &self.sweep {
+            self.sweep_pos += swept;
+            // the impulse view still holds the buffer, dropping it here does not free it
+            if self.sweep_pos == sweep.len() {
+                self.sweep = None;
+            }
+        }
+        if let Some(i) = burst_at {
+            let time = ps.last_frame_time().wrapping_add(i as u32);
+            self.sync.store(time as u64, Ordering::Release);
         }
 
         // Continue as normal
@@ -114,75 +260,161 @@ impl jack::ProcessHandler for SineProcessor {
     }
 }
 
-impl<'a> PortConnector for SineGen<'a> {
+impl<'a> PortConnector for SignalGen<'a> {
     fn connect_to<P: PortName + PortConnector>(&self, client: &P) -> Result<()> {
-        for i in 1..3 {
-            self.jack_client
-                .as_client()
-                .connect_ports_by_name(&self.client_port_name(i), &client.client_port_name(i))?
+        connect_ports(self.jack_client.as_client(), self, client)
+    }
+}
+
+impl<'a> PortName for SignalGen<'a> {
+    fn client_port_name(&self, port_index: i64) -> String {
+        let p_name = port_name(self.port_basename, port_index);
+        return format!("{}:{}", self.name, p_name);
+    }
+
+    fn port_count(&self) -> usize {
+        self.channels
+    }
+}
+
+// Streams a file loaded in the UI to its outputs, driven by transport commands.
+pub struct FilePlayer<'a> {
+    pub name: &'a str,
+    pub port_basename: &'a str,
+    pub sample_rate: usize,
+    pub channels: usize,
+    jack_client: AsyncClient<Notifier, PlayerProcessor>,
+}
+
+impl<'a> FilePlayer<'a> {
+    pub fn new(
+        name: &'a str,
+        port_spec: AudioOut,
+        channels: usize,
+        commands: crossbeam_channel::Receiver<PlayerCommand>,
+        playhead: Playhead,
+        status: Arc<JackStatus>,
+    ) -> Result<FilePlayer<'a>> {
+        let port_basename = "out";
+        let (client, ports) = make_client(name, port_basename, port_spec, channels)?;
+
+        let sample_rate = client.sample_rate();
+        let process = PlayerProcessor {
+            sample_rate,
+            ports,
+            player: Player::new(sample_rate, playhead),
+            commands,
+        };
+
+        let jack_client = client.activate_async(Notifier { status }, process)?;
+
+        Ok(FilePlayer {
+            name,
+            port_basename,
+            sample_rate,
+            channels,
+            jack_client,
+        })
+    }
+}
+
+struct PlayerProcessor {
+    sample_rate: usize,
+    ports: Vec<jack::Port<AudioOut>>,
+    player: Player,
+    commands: crossbeam_channel::Receiver<PlayerCommand>,
+}
+
+impl jack::ProcessHandler for PlayerProcessor {
+    fn process(&mut self, client: &jack::Client, ps: &jack::ProcessScope) -> jack::Control {
+        let sample_rate = client.sample_rate();
+        if sample_rate != self.sample_rate {
+            self.sample_rate = sample_rate;
+            self.player.set_sample_rate(sample_rate);
+        }
+        while let Ok(command) = self.commands.try_recv() {
+            self.player.command(command);
         }
-        Ok(())
+
+        let outs = self.ports.iter_mut().map(|p| p.as_mut_slice(ps));
+        self.player.fill(outs);
+
+        jack::Control::Continue
+    }
+}
+
+impl<'a> PortConnector for FilePlayer<'a> {
+    fn connect_to<P: PortName + PortConnector>(&self, client: &P) -> Result<()> {
+        connect_ports(self.jack_client.as_client(), self, client)
     }
 }
 
-impl<'a> PortName for SineGen<'a> {
+impl<'a> PortName for FilePlayer<'a> {
     fn client_port_name(&self, port_index: i64) -> String {
         let p_name = port_name(self.port_basename, port_index);
         return format!("{}:{}", self.name, p_name);
     }
+
+    fn port_count(&self) -> usize {
+        self.channels
+    }
 }
 
 pub struct FFTProc<'a> {
     pub name: &'a str,
     pub port_basename: &'a str,
     pub sample_rate: usize,
+    pub channels: usize,
     fft_size: usize,
-    jack_client: AsyncClient<(), FFTProcessor>,
+    jack_client: AsyncClient<Notifier, FFTProcessor>,
 }
 
 impl<'a> FFTProc<'a> {
     pub fn new(
         name: &'a str,
-        port_spec_1: AudioIn,
-        port_spec_2: AudioIn,
+        port_spec: AudioIn,
+        channels: usize,
         fft_size: usize,
-        thread_tx: crossbeam_channel::Sender<Vec<f32>>,
+        thread_tx: crossbeam_channel::Sender<Message>,
+        commands: crossbeam_channel::Receiver<AnalyzerCommand>,
+        sync: SyncMarker,
+        status: Arc<JackStatus>,
     ) -> Result<FFTProc<'a>> {
         let port_basename = "in";
-        let (client, port_1, port_2) = make_client(name, port_basename, port_spec_1, port_spec_2)?;
+        let (client, ports) = make_client(name, port_basename, port_spec, channels)?;
 
         let sample_rate = client.sample_rate();
-        let frame_size = client.buffer_size() as usize;
-        let ring_buf_size = fft_size * 10;
+        status
+            .sample_rate
+            .store(sample_rate as u64, Ordering::Release);
+        let ring_buf_size = fft_size * channels * 10;
         let rb = RingBuffer::<f32>::new(ring_buf_size);
         let (prod, cons) = rb.split();
+        let clock = Arc::new(AtomicU64::new(0));
 
         let process = FFTProcessor {
-            port_1,
-            port_2,
-            tmp_buff: [0.0; AUDIO_BUFF_SIZE],
+            ports,
+            tmp_buff: vec![0.0; AUDIO_BUFF_SIZE * channels],
             ring_buf: prod,
-            frame_size,
+            clock: clock.clone(),
+            status: status.clone(),
         };
 
-        let jack_client = client.activate_async((), process)?;
-
-        assert!(
-            frame_size < AUDIO_BUFF_SIZE,
-            "Jack Frame Size {} greater than Audio Buffer Size {}",
-            frame_size,
-            AUDIO_BUFF_SIZE
-        );
+        let notifier = Notifier {
+            status: status.clone(),
+        };
+        let jack_client = client.activate_async(notifier, process)?;
 
         let fft_proc = FFTProc {
             name,
             port_basename,
             sample_rate,
+            channels,
             jack_client,
             fft_size,
         };
 
-        fft_proc.run(cons, thread_tx);
+        fft_proc.run(cons, clock, sync, status, thread_tx, commands);
 
         return Ok(fft_proc);
     }
@@ -190,7 +422,11 @@ impl<'a> FFTProc<'a> {
     fn run(
         &self,
         mut ring_buf: ringbuf::Consumer<f32>,
-        thread_tx: crossbeam_channel::Sender<Vec<f32>>,
+        clock: Arc<AtomicU64>,
+        sync: SyncMarker,
+        status: Arc<JackStatus>,
+        thread_tx: crossbeam_channel::Sender<Message>,
+        commands: crossbeam_channel::Receiver<AnalyzerCommand>,
     ) {
         let sleep_millis = Duration::from_millis(5);
         let mut planner = RealFftPlanner::new();
@@ -198,9 +434,27 @@ impl<'a> FFTProc<'a> {
 
         let mut spec_buf = [Complex32::new(0.0, 0.0); FFT_MAX_BUFF_SIZE];
         let mut sig_buf = [0.0; FFT_MAX_SIZE];
+        let channels = self.channels;
+        let mut raw_buf = vec![0.0; FFT_MAX_SIZE * channels];
+        let mut capture: Option<Capture> = None;
+        let mut mode = AnalysisMode::Spectrum;
+        let mut analysis = Analysis::Spectrum;
+        let mut complex_spectra = false;
+        let mut raw_samples = false;
+        let mut cepstral: Option<Cepstral> = None;
+        let mut lpc: Option<Lpc> = None;
+        let mut lpc_params: Option<LpcParams> = None;
+        let mut imd: Option<Imd> = None;
+        let mut imd_standard: Option<ImdStandard> = None;
+        let mut chan_buf = [0.0; FFT_MAX_SIZE];
         let spec_buf_len = spec_buf.len() as f32;
         let fft_size = self.fft_size;
         let fft_buff_size = fft_used_buff_size(fft_size);
+        let mut sample_rate = self.sample_rate;
+        let mut spectrum_axis = FreqAxis::Linear {
+            start: 0.0,
+            step: sample_rate as f32 / fft_size as f32,
+        };
 
         // Hanning window for now
         let mut window: [f32; FFT_MAX_SIZE] = [0.0; FFT_MAX_SIZE];
@@ -211,58 +465,260 @@ impl<'a> FFTProc<'a> {
         }
 
         thread::spawn(move || loop {
-            while ring_buf.len() < fft_size {
+            while let Ok(command) = commands.try_recv() {
+                match command {
+                    AnalyzerCommand::Capture(params) => {
+                        capture = Some(Capture::new(params, channels));
+                    }
+                    AnalyzerCommand::SetMode(new_mode) => {
+                        mode = new_mode;
+                        analysis = Analysis::new(mode, sample_rate);
+                    }
+                    AnalyzerCommand::ComplexSpectra(on) => complex_spectra = on,
+                    AnalyzerCommand::RawSamples(on) => raw_samples = on,
+                    AnalyzerCommand::Cepstrum(on) => {
+                        cepstral = match on {
+                            true => Some(Cepstral::new(&mut planner, fft_size, sample_rate)),
+                            false => None,
+                        };
+                    }
+                    AnalyzerCommand::Lpc(params) => {
+                        lpc_params = params;
+                        lpc = params.map(|p| Lpc::new(p, sample_rate, fft_size));
+                    }
+                    AnalyzerCommand::Imd(standard) => {
+                        imd_standard = standard;
+                        imd = standard.map(|s| Imd::new(s, sample_rate));
+                    }
+                }
+            }
+
+            // Everything that depends on the sample rate starts over at the new one
+            let rate = status.sample_rate.load(Ordering::Acquire) as usize;
+            if rate != 0 && rate != sample_rate {
+                sample_rate = rate;
+                spectrum_axis = FreqAxis::Linear {
+                    start: 0.0,
+                    step: sample_rate as f32 / fft_size as f32,
+                };
+                analysis = Analysis::new(mode, sample_rate);
+                if cepstral.is_some() {
+                    cepstral = Some(Cepstral::new(&mut planner, fft_size, sample_rate));
+                }
+                lpc = lpc_params.map(|p| Lpc::new(p, sample_rate, fft_size));
+                imd = imd_standard.map(|s| Imd::new(s, sample_rate));
+                // the sweep being measured was made for the old rate
+                capture = None;
+                thread_tx.send(Message::SampleRate(sample_rate)).unwrap();
+            }
+
+            let raw_size = fft_size * channels;
+            while ring_buf.len() < raw_size {
                 thread::sleep(sleep_millis);
             }
-            ring_buf.pop_slice(&mut sig_buf[0..fft_size]);
+            // Frame time of the first sample of the block. The clock is read on both
+            // sides of the queue length so a callback in between is noticed.
+            let block_start = loop {
+                let end = clock.load(Ordering::Acquire);
+                let queued = ring_buf.len() / channels;
+                if clock.load(Ordering::Acquire) == end {
+                    break (end as u32).wrapping_sub(queued as u32);
+                }
+            };
+            ring_buf.pop_slice(&mut raw_buf[0..raw_size]);
+
+            if let Some(cap) = capture.as_mut() {
+                cap.push(&raw_buf[0..raw_size]);
+                if cap.is_done() {
+                    // Deconvolution takes a while, keep it off the analysis loop
+                    let cap = capture.take().unwrap();
+                    let tx = thread_tx.clone();
+                    thread::spawn(move || tx.send(Message::Impulse(cap.finish())).unwrap());
+                }
+            }
 
-            // window
-            for i in 0..fft_size {
-                sig_buf[i] = sig_buf[i] * window[i];
+            if raw_samples {
+                let samples = (0..channels)
+                    .map(|c| {
+                        raw_buf[0..raw_size]
+                            .iter()
+                            .skip(c)
+                            .step_by(channels)
+                            .cloned()
+                            .collect()
+                    })
+                    .collect();
+                let marker = sync.load(Ordering::Acquire);
+                let offset = (marker as u32).wrapping_sub(block_start) as usize;
+                let sync = if marker != NO_SYNC && offset < fft_size {
+                    Some(offset)
+                } else {
+                    None
+                };
+                thread_tx
+                    .send(Message::Samples(SampleBlock {
+                        channels: samples,
+                        sync,
+                    }))
+                    .unwrap();
             }
 
-            fft.process(&mut sig_buf[0..fft_size], &mut spec_buf[0..fft_buff_size])
-                .unwrap();
-            let out: Vec<f32> = spec_buf
-                .iter()
-                .take(fft_buff_size)
-                .into_iter()
-                .map(|x| x.norm_sqr().sqrt() / spec_buf_len)
-                .collect();
+            if complex_spectra {
+                let spectra = (0..channels)
+                    .map(|c| {
+                        for i in 0..fft_size {
+                            chan_buf[i] = raw_buf[i * channels + c] * window[i];
+                        }
+                        let mut spec = vec![Complex32::new(0.0, 0.0); fft_buff_size];
+                        fft.process(&mut chan_buf[0..fft_size], &mut spec).unwrap();
+                        spec
+                    })
+                    .collect();
+                thread_tx
+                    .send(Message::Spectra(ComplexFrame {
+                        channels: spectra,
+                        axis: spectrum_axis,
+                    }))
+                    .unwrap();
+            }
 
-            thread_tx.send(out).unwrap();
+            // the spectrum is taken over the sum of all inputs
+            for (i, frame) in raw_buf[0..raw_size].chunks(channels).enumerate() {
+                sig_buf[i] = frame.iter().sum();
+            }
+
+            if let Some(cepstral) = cepstral.as_mut() {
+                let frame = cepstral.process(&sig_buf[0..fft_size], &window[0..fft_size]);
+                thread_tx.send(Message::Cepstrum(frame)).unwrap();
+            }
+
+            if let Some(lpc) = lpc.as_ref() {
+                let frame = lpc.process(&sig_buf[0..fft_size]);
+                thread_tx.send(Message::Lpc(frame)).unwrap();
+            }
+
+            if let Some(result) = imd.as_mut().and_then(|imd| imd.push(&sig_buf[0..fft_size])) {
+                thread_tx.send(Message::Imd(result)).unwrap();
+            }
+
+            match &mut analysis {
+                Analysis::Psd(welch) => {
+                    for frame in welch.push(&sig_buf[0..fft_size]) {
+                        thread_tx.send(Message::Tick(frame)).unwrap();
+                    }
+                }
+                Analysis::Multitaper(multitaper) => {
+                    for frame in multitaper.push(&sig_buf[0..fft_size]) {
+                        thread_tx.send(Message::Tick(frame)).unwrap();
+                    }
+                }
+                Analysis::Zoom(zoom) => {
+                    for frame in zoom.push(&sig_buf[0..fft_size]) {
+                        thread_tx.send(Message::Tick(frame)).unwrap();
+                    }
+                }
+                Analysis::MultiRes(multires) => {
+                    for frame in multires.push(&sig_buf[0..fft_size]) {
+                        thread_tx.send(Message::Tick(frame)).unwrap();
+                    }
+                }
+                Analysis::Cwt(cwt) => {
+                    for frame in cwt.push(&sig_buf[0..fft_size]) {
+                        thread_tx.send(Message::Tick(frame)).unwrap();
+                    }
+                }
+                Analysis::Spectrum => {
+                    // window
+                    for i in 0..fft_size {
+                        sig_buf[i] = sig_buf[i] * window[i];
+                    }
+
+                    fft.process(&mut sig_buf[0..fft_size], &mut spec_buf[0..fft_buff_size])
+                        .unwrap();
+                    let out: Vec<f32> = spec_buf
+                        .iter()
+                        .take(fft_buff_size)
+                        .into_iter()
+                        .map(|x| x.norm_sqr().sqrt() / spec_buf_len)
+                        .collect();
+
+                    thread_tx
+                        .send(Message::Tick(Frame {
+                            bins: out,
+                            axis: spectrum_axis,
+                            scale: Scale::Amplitude,
+                            period: fft_size as f32 / sample_rate as f32,
+                        }))
+                        .unwrap();
+                }
+            }
         });
     }
 }
 
+// What the analysis thread does with each block of samples.
+enum Analysis {
+    Spectrum,
+    Psd(Welch),
+    Multitaper(Multitaper),
+    Zoom(ZoomFft),
+    MultiRes(MultiRes),
+    Cwt(Cwt),
+}
+
+impl Analysis {
+    fn new(mode: AnalysisMode, sample_rate: usize) -> Analysis {
+        match mode {
+            AnalysisMode::Spectrum => Analysis::Spectrum,
+            AnalysisMode::Psd(params) => Analysis::Psd(Welch::new(params, sample_rate)),
+            AnalysisMode::Multitaper(params) => {
+                Analysis::Multitaper(Multitaper::new(params, sample_rate))
+            }
+            AnalysisMode::Zoom(params) => Analysis::Zoom(ZoomFft::new(params, sample_rate)),
+            AnalysisMode::MultiRes(params) => {
+                Analysis::MultiRes(MultiRes::new(params, sample_rate))
+            }
+            AnalysisMode::Cwt(params) => Analysis::Cwt(Cwt::new(params, sample_rate)),
+        }
+    }
+}
+
 struct FFTProcessor {
-    port_1: jack::Port<AudioIn>,
-    port_2: jack::Port<AudioIn>,
-    tmp_buff: [f32; AUDIO_BUFF_SIZE],
+    ports: Vec<jack::Port<AudioIn>>,
+    tmp_buff: Vec<f32>,
     ring_buf: ringbuf::Producer<f32>,
-    frame_size: usize,
+    // Jack frame time just after the newest sample in the ring buffer
+    clock: Arc<AtomicU64>,
+    status: Arc<JackStatus>,
 }
 
 impl jack::ProcessHandler for FFTProcessor {
     fn process(&mut self, _: &jack::Client, ps: &jack::ProcessScope) -> jack::Control {
-        let in_a_p = self.port_1.as_slice(ps);
-        let in_b_p = self.port_2.as_slice(ps);
-        for i in 0..self.frame_size {
-            self.tmp_buff[i] = in_a_p[i] + in_b_p[i];
+        let channels = self.ports.len();
+        // Jack may change its buffer size at any time, so take whatever this cycle has
+        let frames = ps.n_frames() as usize;
+        // frames are pushed interleaved, the analysis thread splits the channels
+        for start in (0..frames).step_by(AUDIO_BUFF_SIZE) {
+            let len = (frames - start).min(AUDIO_BUFF_SIZE);
+            for (c, port) in self.ports.iter().enumerate() {
+                for (i, x) in port.as_slice(ps)[start..start + len].iter().enumerate() {
+                    self.tmp_buff[i * channels + c] = *x;
+                }
+            }
+            self.ring_buf.push_slice(&self.tmp_buff[0..len * channels]);
         }
-        self.ring_buf.push_slice(&self.tmp_buff[0..self.frame_size]);
+        let end = ps.last_frame_time().wrapping_add(frames as u32);
+        self.clock.store(end as u64, Ordering::Release);
+        self.status
+            .buffer_size
+            .store(frames as u64, Ordering::Relaxed);
         jack::Control::Continue
     }
 }
 
 impl<'a> PortConnector for FFTProc<'a> {
     fn connect_to<P: PortName + PortConnector>(&self, client: &P) -> Result<()> {
-        for i in 1..3 {
-            self.jack_client
-                .as_client()
-                .connect_ports_by_name(&self.client_port_name(i), &client.client_port_name(i))?
-        }
-        Ok(())
+        connect_ports(self.jack_client.as_client(), self, client)
     }
 }
 
@@ -271,38 +727,230 @@ impl<'a> PortName for FFTProc<'a> {
         let p_name = port_name(self.port_basename, port_index);
         return format!("{}:{}", self.name, p_name);
     }
+
+    fn port_count(&self) -> usize {
+        self.channels
+    }
 }
 
-pub struct PlaybackSystem<'a> {
-    name: &'a str,
-    port_basename: &'a str,
+// Full names of the ports of a client, in port order.
+pub fn port_names<P: PortName>(client: &P) -> Vec<String> {
+    (1..=client.port_count() as i64)
+        .map(|i| client.client_port_name(i))
+        .collect()
 }
 
-impl<'a> PlaybackSystem<'a> {
-    pub fn new() -> PlaybackSystem<'a> {
-        PlaybackSystem {
-            name: "system",
-            port_basename: "playback",
-        }
+// What the patchbay thread is woken up for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PortChange {
+    Ports,
+    Connections,
+}
+
+// Notices ports coming and going and connections changing. Nothing may be connected
+// from inside a notification, so it only wakes the patchbay thread.
+struct PortWatcher {
+    changed: crossbeam_channel::Sender<PortChange>,
+}
+
+impl jack::NotificationHandler for PortWatcher {
+    fn port_registration(&mut self, _: &jack::Client, _: jack::PortId, _: bool) {
+        self.changed.try_send(PortChange::Ports).ok();
+    }
+
+    fn ports_connected(&mut self, _: &jack::Client, _: jack::PortId, _: jack::PortId, _: bool) {
+        self.changed.try_send(PortChange::Connections).ok();
     }
 }
 
-impl<'a> PortConnector for PlaybackSystem<'a> {
-    fn connect_to<P: PortName + PortConnector>(&self, client: &P) -> Result<()> {
-        client.connect_to(self)?;
-        Ok(())
+// A client without ports of its own that lists and connects everybody's audio
+// ports. The connect rules are applied on startup and again whenever ports appear.
+// A rule only connects a pair of ports once while both exist, so connections the
+// user made or broke in between are left alone.
+pub struct Patchbay {
+    jack_client: Arc<AsyncClient<PortWatcher, ()>>,
+}
+
+impl Patchbay {
+    // inputs are the analyzer ports, outputs the ports of each client that plays.
+    pub fn new(
+        name: &str,
+        rules: Vec<ConnectRule>,
+        inputs: Vec<String>,
+        outputs: Vec<Vec<String>>,
+        thread_tx: crossbeam_channel::Sender<Message>,
+        commands: crossbeam_channel::Receiver<PatchCommand>,
+    ) -> Result<Patchbay> {
+        let (client, _status) = jack::Client::new(name, jack::ClientOptions::NO_START_SERVER)?;
+        let (changed_tx, changed_rx) = crossbeam_channel::bounded(GEN_COMMANDS);
+        let watcher = PortWatcher {
+            changed: changed_tx,
+        };
+        let jack_client = Arc::new(client.activate_async(watcher, ())?);
+
+        let patchbay = Patchbay { jack_client };
+        patchbay.run(rules, inputs, outputs, changed_rx, thread_tx, commands);
+        Ok(patchbay)
+    }
+
+    fn run(
+        &self,
+        rules: Vec<ConnectRule>,
+        inputs: Vec<String>,
+        outputs: Vec<Vec<String>>,
+        changed: crossbeam_channel::Receiver<PortChange>,
+        thread_tx: crossbeam_channel::Sender<Message>,
+        commands: crossbeam_channel::Receiver<PatchCommand>,
+    ) {
+        let jack_client = self.jack_client.clone();
+        // notifications come in bursts, a client registers all its ports at once
+        let settle = Duration::from_millis(50);
+
+        thread::spawn(move || {
+            let client = jack_client.as_client();
+            let mut errors = vec![];
+            let mut made = HashSet::new();
+            let mut apply = true;
+            loop {
+                if apply {
+                    made = apply_rules(client, &rules, &inputs, &outputs, &made, &mut errors);
+                }
+                let graph = port_graph(client, &inputs, &outputs, std::mem::take(&mut errors));
+                if thread_tx.send(Message::Ports(graph)).is_err() {
+                    return;
+                }
+
+                apply = false;
+                crossbeam_channel::select! {
+                    recv(changed) -> change => match change {
+                        Ok(change) => apply = change == PortChange::Ports,
+                        Err(_) => return,
+                    },
+                    recv(commands) -> command => {
+                        let result = match command {
+                            Ok(PatchCommand::Connect(from, to)) => client
+                                .connect_ports_by_name(&from, &to)
+                                .map_err(|e| format!("connecting {} to {}: {}", from, to, e)),
+                            Ok(PatchCommand::Disconnect(from, to)) => client
+                                .disconnect_ports_by_name(&from, &to)
+                                .map_err(|e| format!("disconnecting {} from {}: {}", from, to, e)),
+                            Ok(PatchCommand::Refresh) => Ok(()),
+                            Err(_) => return,
+                        };
+                        errors.extend(result.err());
+                    },
+                }
+                thread::sleep(settle);
+                while let Ok(change) = changed.try_recv() {
+                    apply |= change == PortChange::Ports;
+                }
+            }
+        });
     }
 }
 
-impl<'a> PortName for PlaybackSystem<'a> {
-    fn client_port_name(&self, port_index: i64) -> String {
-        let p_name = port_name(self.port_basename, port_index);
-        return format!("{}:{}", self.name, p_name);
+fn audio_ports(client: &jack::Client, pattern: Option<&str>, flags: PortFlags) -> Vec<String> {
+    client.ports(pattern, Some(AudioIn::default().jack_port_type()), flags)
+}
+
+fn is_connected(client: &jack::Client, from: &str, to: &str) -> bool {
+    client
+        .port_by_name(from)
+        .map_or(false, |p| p.is_connected_to(to).unwrap_or(false))
+}
+
+// Connects the pairs the rules match that were not matched on the last pass, made
+// holds the pairs handled then. Returns the pairs handled now, pairs with a port
+// that went away drop out so the port is connected again when it comes back.
+fn apply_rules(
+    client: &jack::Client,
+    rules: &[ConnectRule],
+    inputs: &[String],
+    outputs: &[Vec<String>],
+    made: &HashSet<(String, String)>,
+    errors: &mut Vec<String>,
+) -> HashSet<(String, String)> {
+    let mut handled = HashSet::new();
+    let mut connect = |from: &String, to: &String| {
+        let pair = (from.clone(), to.clone());
+        // made before, the user may have broken it since
+        if made.contains(&pair) || is_connected(client, from, to) {
+            handled.insert(pair);
+            return;
+        }
+        match client.connect_ports_by_name(from, to) {
+            Ok(()) => {
+                handled.insert(pair);
+            }
+            Err(e) => errors.push(format!("connecting {} to {}: {}", from, to, e)),
+        }
+    };
+    // each in rule carries on at the first input the rules before it left unused
+    let mut next_input = 0;
+    for rule in rules {
+        let pattern = rule.anchored();
+        match rule.direction {
+            Direction::In => {
+                let found = audio_ports(client, Some(&pattern), PortFlags::IS_OUTPUT);
+                for (from, to) in found.iter().zip(inputs[next_input..].iter()) {
+                    connect(from, to);
+                }
+                next_input += found.len().min(inputs.len() - next_input);
+            }
+            Direction::Out => {
+                let found = audio_ports(client, Some(&pattern), PortFlags::IS_INPUT);
+                for ports in outputs {
+                    for (from, to) in ports.iter().zip(found.iter()) {
+                        connect(from, to);
+                    }
+                }
+            }
+        }
+    }
+    handled
+}
+
+fn port_graph(
+    client: &jack::Client,
+    inputs: &[String],
+    outputs: &[Vec<String>],
+    errors: Vec<String>,
+) -> PortGraph {
+    let outputs: Vec<String> = outputs.iter().flatten().cloned().collect();
+    let sources = audio_ports(client, None, PortFlags::IS_OUTPUT);
+    let destinations = audio_ports(client, None, PortFlags::IS_INPUT);
+    let mut connections = vec![];
+    for to in inputs {
+        for from in sources.iter().filter(|from| is_connected(client, from, to)) {
+            connections.push((from.clone(), to.clone()));
+        }
+    }
+    for from in outputs.iter() {
+        for to in destinations
+            .iter()
+            .filter(|to| is_connected(client, from, to))
+        {
+            connections.push((from.clone(), to.clone()));
+        }
+    }
+    // our outputs feeding our inputs show up from both ends
+    connections.sort();
+    connections.dedup();
+
+    PortGraph {
+        inputs: inputs.to_vec(),
+        outputs,
+        sources,
+        destinations,
+        connections,
+        errors,
     }
 }
 
 pub trait PortName {
     fn client_port_name(&self, port_index: i64) -> String;
+    // Ports are numbered from 1 up to this
+    fn port_count(&self) -> usize;
 }
 
 pub trait PortConnector {
@@ -0,0 +1,202 @@
+use super::common::*;
+use num_complex::Complex32;
+use rustfft::{Fft, FftPlanner};
+use std::sync::Arc;
+
+// Samples reported per transform. The transform is longer, it is sized so the
+// longest wavelet fits on both sides of the reported block, up to MAX_LENGTH.
+const BLOCK: usize = 8192;
+const MAX_LENGTH: usize = 1 << 18;
+const ROWS_PER_BLOCK: usize = 16;
+// Standard deviations of a wavelet's envelope kept, in time and in frequency
+const SUPPORT: f32 = 5.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CwtParams {
+    pub voices: usize,
+    // Cycles of the Morlet wavelet per standard deviation of its envelope, more
+    // cycles trade time resolution for frequency resolution
+    pub cycles: f32,
+    pub min_hz: f32,
+    pub max_hz: f32,
+}
+
+impl Default for CwtParams {
+    fn default() -> Self {
+        CwtParams {
+            voices: 12,
+            cycles: 6.0,
+            min_hz: 20.0,
+            max_hz: 20000.0,
+        }
+    }
+}
+
+impl CwtParams {
+    pub fn axis(&self) -> FreqAxis {
+        FreqAxis::Log {
+            start: self.min_hz,
+            ratio: 2f32.powf(1.0 / self.voices as f32),
+        }
+    }
+
+    pub fn scales(&self, sample_rate: usize) -> usize {
+        // keep the wavelets clear of nyquist
+        let max_hz = self.max_hz.min(0.45 * sample_rate as f32);
+        ((max_hz / self.min_hz).log2() * self.voices as f32) as usize + 1
+    }
+
+    // Time resolution in seconds, the standard deviation of the envelope, at hz.
+    pub fn time_resolution(&self, hz: f32) -> f32 {
+        self.cycles / (2.0 * std::f32::consts::PI * hz)
+    }
+
+    // Most cycles for which the lowest wavelet still fits in MAX_LENGTH.
+    pub fn max_cycles(&self, sample_rate: usize) -> f32 {
+        let margin = (MAX_LENGTH - BLOCK) / 2;
+        margin as f32 * 2.0 * std::f32::consts::PI * self.min_hz / (SUPPORT * sample_rate as f32)
+    }
+
+    // Samples transformed at once, the reported block with the lowest wavelet's
+    // support on either side.
+    pub fn length(&self, sample_rate: usize) -> usize {
+        let margin = (SUPPORT * self.time_resolution(self.min_hz) * sample_rate as f32).ceil();
+        (BLOCK + 2 * margin as usize)
+            .next_power_of_two()
+            .min(MAX_LENGTH)
+    }
+
+    // How far the reported block lags the newest samples, in seconds.
+    pub fn delay(&self, sample_rate: usize) -> f32 {
+        (self.length(sample_rate) - BLOCK) as f32 / 2.0 / sample_rate as f32
+    }
+}
+
+// A wavelet's Gaussian frequency response over the positive FFT bins it covers.
+// Its output is only band wide, so it is brought back to time with a short inverse
+// FFT of the band shifted down to zero, which gives every decimation-th sample.
+struct Wavelet {
+    // first non negligible bin
+    lo: usize,
+    response: Vec<f32>,
+    inv: Arc<dyn Fft<f32>>,
+    decimation: usize,
+}
+
+// Continuous wavelet transform with analytic Morlet wavelets, computed by
+// multiplying the spectrum of the signal with each wavelet's spectrum. Every
+// block of input gives ROWS_PER_BLOCK frames, one bin per scale, lowest first.
+pub struct Cwt {
+    axis: FreqAxis,
+    sample_rate: usize,
+    length: usize,
+    fwd: Arc<dyn Fft<f32>>,
+    wavelets: Vec<Wavelet>,
+    history: Vec<f32>,
+    pending: Vec<f32>,
+    spec: Vec<Complex32>,
+    buf: Vec<Complex32>,
+}
+
+impl Cwt {
+    pub fn new(params: CwtParams, sample_rate: usize) -> Cwt {
+        let params = CwtParams {
+            cycles: params.cycles.min(params.max_cycles(sample_rate)),
+            ..params
+        };
+        let mut planner = FftPlanner::<f32>::new();
+        let axis = params.axis();
+        let length = params.length(sample_rate);
+        let bin_hz = sample_rate as f32 / length as f32;
+        // at least one output sample in every row's hop
+        let min_band = length / (BLOCK / ROWS_PER_BLOCK);
+
+        let wavelets = (0..params.scales(sample_rate))
+            .map(|s| {
+                let center = axis.hz(s);
+                let sigma = center / params.cycles;
+                let lo = (((center - SUPPORT * sigma) / bin_hz).floor().max(1.0)) as usize;
+                let hi = (((center + SUPPORT * sigma) / bin_hz).ceil() as usize).min(length / 2);
+                // a peak gain of 1 reads A / 2 for a sine of amplitude A, like the spectrum
+                let response: Vec<f32> = (lo..=hi)
+                    .map(|k| {
+                        let d = (k as f32 * bin_hz - center) / sigma;
+                        (-0.5 * d * d).exp()
+                    })
+                    .collect();
+                let band = response.len().next_power_of_two().max(min_band);
+                Wavelet {
+                    lo,
+                    response,
+                    inv: planner.plan_fft_inverse(band),
+                    decimation: length / band,
+                }
+            })
+            .collect();
+
+        Cwt {
+            axis,
+            sample_rate,
+            length,
+            fwd: planner.plan_fft_forward(length),
+            wavelets,
+            history: vec![0.0; length],
+            pending: Vec::with_capacity(BLOCK * 2),
+            spec: vec![Complex32::new(0.0, 0.0); length],
+            buf: vec![Complex32::new(0.0, 0.0); length],
+        }
+    }
+
+    pub fn push(&mut self, samples: &[f32]) -> Vec<Frame> {
+        self.pending.extend_from_slice(samples);
+        let mut frames = vec![];
+        while self.pending.len() >= BLOCK {
+            self.history.drain(0..BLOCK);
+            self.history.extend(self.pending.drain(0..BLOCK));
+            frames.extend(self.transform());
+        }
+        frames
+    }
+
+    fn transform(&mut self) -> Vec<Frame> {
+        for (s, x) in self.spec.iter_mut().zip(self.history.iter()) {
+            *s = Complex32::new(*x, 0.0);
+        }
+        self.fwd.process(&mut self.spec);
+
+        let hop = BLOCK / ROWS_PER_BLOCK;
+        let first = (self.length - BLOCK) / 2;
+        let mut rows = vec![vec![0.0; self.wavelets.len()]; ROWS_PER_BLOCK];
+        let norm = 1.0 / self.length as f32;
+        for (s, w) in self.wavelets.iter().enumerate() {
+            let buf = &mut self.buf[0..self.length / w.decimation];
+            buf.iter_mut().for_each(|b| *b = Complex32::new(0.0, 0.0));
+            // the wavelets are analytic, negative frequencies stay at zero. Shifting
+            // the band down only turns the phase of the output, not its magnitude.
+            for (i, g) in w.response.iter().enumerate() {
+                buf[i] = self.spec[w.lo + i] * *g;
+            }
+            w.inv.process(buf);
+
+            // each row shows the largest magnitude within its hop, so a click
+            // between two rows is not lost
+            let (from, step) = (first / w.decimation, hop / w.decimation);
+            for (r, row) in rows.iter_mut().enumerate() {
+                let at = from + r * step;
+                row[s] = buf[at..at + step]
+                    .iter()
+                    .map(|c| c.norm() * norm)
+                    .fold(0.0, f32::max);
+            }
+        }
+
+        rows.into_iter()
+            .map(|bins| Frame {
+                bins,
+                axis: self.axis,
+                scale: Scale::Amplitude,
+                period: hop as f32 / self.sample_rate as f32,
+            })
+            .collect()
+    }
+}
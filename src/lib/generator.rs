@@ -0,0 +1,756 @@
+use super::common::*;
+use super::imd::ImdStandard;
+use std::f64::consts::PI;
+
+// Corner of the leaky integrator that turns white noise into brown noise
+const BROWN_CORNER_HZ: f64 = 5.0;
+// RMS of the pink noise filter below fed with unit RMS white noise, at 48 kHz
+const PINK_RMS: f32 = 3.06;
+pub const MIN_MLS_ORDER: u32 = 2;
+pub const MAX_MLS_ORDER: u32 = 20;
+pub const MIN_LEVEL_DB: f32 = -100.0;
+// Time constant of the glides that follow a change of level, mute, phase offset or
+// frequency, short enough to feel immediate and long enough not to click
+const GLIDE_SECS: f64 = 0.005;
+// MIDI controllers the generator follows: channel volume sets the level, sound
+// controller 1 picks the waveform
+const CC_VOLUME: u8 = 7;
+const CC_WAVEFORM: u8 = 70;
+const CC_ALL_NOTES_OFF: u8 = 123;
+
+// Feedback masks of maximal length Galois LFSRs, by order starting at 2.
+const MLS_TAPS: [u32; (MAX_MLS_ORDER - MIN_MLS_ORDER + 1) as usize] = [
+    0x3, 0x6, 0xC, 0x14, 0x30, 0x60, 0xB8, 0x110, 0x240, 0x500, 0x829, 0x100D, 0x2015, 0x6000,
+    0xD008, 0x12000, 0x20400, 0x40023, 0x90000,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Waveform {
+    Off,
+    Sine,
+    White,
+    Pink,
+    Brown,
+    Square,
+    Saw,
+    Triangle,
+    // Maximum length sequence of +-1, repeating every 2^order - 1 samples
+    Mls,
+    Dc,
+    // Sine swept between two frequencies
+    Sweep,
+    // Tone pair of an intermodulation distortion test
+    Imd(ImdStandard),
+}
+
+pub const WAVEFORMS: [Waveform; 13] = [
+    Waveform::Off,
+    Waveform::Sine,
+    Waveform::White,
+    Waveform::Pink,
+    Waveform::Brown,
+    Waveform::Square,
+    Waveform::Saw,
+    Waveform::Triangle,
+    Waveform::Mls,
+    Waveform::Dc,
+    Waveform::Sweep,
+    Waveform::Imd(ImdStandard::Smpte),
+    Waveform::Imd(ImdStandard::Ccif),
+];
+
+impl Waveform {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Waveform::Off => "off",
+            Waveform::Sine => "sine",
+            Waveform::White => "white noise",
+            Waveform::Pink => "pink noise",
+            Waveform::Brown => "brown noise",
+            Waveform::Square => "square",
+            Waveform::Saw => "saw",
+            Waveform::Triangle => "triangle",
+            Waveform::Mls => "MLS",
+            Waveform::Dc => "DC",
+            Waveform::Sweep => "sweep",
+            Waveform::Imd(ImdStandard::Smpte) => "SMPTE/DIN IMD",
+            Waveform::Imd(ImdStandard::Ccif) => "CCIF IMD",
+        }
+    }
+
+    pub fn is_periodic(&self) -> bool {
+        match self {
+            Waveform::Sine | Waveform::Square | Waveform::Saw | Waveform::Triangle => true,
+            _ => false,
+        }
+    }
+
+    // RMS of the waveform at unit gain
+    fn rms(&self) -> f32 {
+        match self {
+            Waveform::Sine | Waveform::Sweep => std::f32::consts::FRAC_1_SQRT_2,
+            Waveform::Saw | Waveform::Triangle => 1.0 / 3f32.sqrt(),
+            // IMD pairs go by their peak, the same as a sine of the level, as the
+            // standards have it
+            Waveform::Imd(_) => std::f32::consts::FRAC_1_SQRT_2,
+            _ => 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SweepLaw {
+    Linear,
+    // equal time per octave
+    Log,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SweepRepeat {
+    // Stops at the end, after finishing the last cycle
+    Once,
+    // Jumps back to the start frequency
+    Repeat,
+    // Turns around and sweeps back
+    PingPong,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SweptSine {
+    pub law: SweepLaw,
+    pub start_hz: f32,
+    pub stop_hz: f32,
+    // seconds from start to stop
+    pub duration: f32,
+    pub repeat: SweepRepeat,
+}
+
+impl Default for SweptSine {
+    fn default() -> Self {
+        SweptSine {
+            law: SweepLaw::Log,
+            start_hz: 20.0,
+            stop_hz: 20000.0,
+            duration: 10.0,
+            repeat: SweepRepeat::Repeat,
+        }
+    }
+}
+
+impl SweptSine {
+    // Frequency a fraction x of the way from start to stop.
+    pub fn hz(&self, x: f64) -> f64 {
+        let (start, stop) = (self.start_hz as f64, self.stop_hz as f64);
+        match self.law {
+            SweepLaw::Linear => start + (stop - start) * x,
+            SweepLaw::Log => start * (stop / start).powf(x),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Pass {
+    Running,
+    // a one-shot sweep holds the stop frequency until the phase wraps
+    LastCycle,
+    Done,
+}
+
+// Gates a periodic waveform into bursts of whole cycles.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Burst {
+    pub enabled: bool,
+    pub on_cycles: u32,
+    pub off_cycles: u32,
+    // raised cosine envelope over the on cycles instead of a hard gate
+    pub shaped: bool,
+}
+
+impl Default for Burst {
+    fn default() -> Self {
+        Burst {
+            enabled: false,
+            on_cycles: 4,
+            off_cycles: 16,
+            shaped: false,
+        }
+    }
+}
+
+// Levels are RMS in dBFS as in AES17, where 0 dBFS is the RMS of a full scale sine.
+// A sine at 0 dBFS peaks at 1, and every waveform at the same level carries the
+// same power, apart from the IMD pairs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelParams {
+    pub waveform: Waveform,
+    pub freq: f32,
+    pub level_db: f32,
+    pub muted: bool,
+    // added to the phase of the periodic waveforms, in degrees
+    pub phase_deg: f32,
+    pub mls_order: u32,
+    pub sweep: SweptSine,
+    pub burst: Burst,
+}
+
+impl ChannelParams {
+    pub fn sine(freq: f32) -> ChannelParams {
+        ChannelParams {
+            waveform: Waveform::Sine,
+            freq,
+            level_db: 0.0,
+            muted: false,
+            phase_deg: 0.0,
+            mls_order: 16,
+            sweep: SweptSine::default(),
+            burst: Burst::default(),
+        }
+    }
+
+    fn gain(&self) -> f32 {
+        if self.muted {
+            return 0.0;
+        }
+        10f32.powf(self.level_db / 20.0) * std::f32::consts::FRAC_1_SQRT_2 / self.waveform.rms()
+    }
+}
+
+// Amplitudes of the two IMD tones, adding up to a peak of 1.
+fn imd_amplitudes(standard: ImdStandard) -> (f32, f32) {
+    let (_, _, ratio) = standard.tones();
+    (ratio / (ratio + 1.0), 1.0 / (ratio + 1.0))
+}
+
+// PolyBLEP residual that rounds off a unit step at phase 0 of a phase running dt per
+// sample, which takes the bulk of the aliasing out of the naive waveforms.
+fn poly_blep(t: f64, dt: f64) -> f64 {
+    if t < dt {
+        let x = t / dt;
+        2.0 * x - x * x - 1.0
+    } else if t > 1.0 - dt {
+        let x = (t - 1.0) / dt;
+        x * x + 2.0 * x + 1.0
+    } else {
+        0.0
+    }
+}
+
+// The integral of poly_blep, for the corners of a triangle.
+fn poly_blamp(t: f64, dt: f64) -> f64 {
+    if t < dt {
+        let x = t / dt - 1.0;
+        -x * x * x / 3.0
+    } else if t > 1.0 - dt {
+        let x = (t - 1.0) / dt + 1.0;
+        x * x * x / 3.0
+    } else {
+        0.0
+    }
+}
+
+// Frequency of a MIDI note, equal temperament with A4 at 440 Hz.
+pub fn note_hz(note: u8) -> f32 {
+    440.0 * 2f32.powf((note as f32 - 69.0) / 12.0)
+}
+
+// Level of a MIDI volume value, on the General MIDI curve.
+fn volume_db(value: u8) -> f32 {
+    (40.0 * (value as f32 / 127.0).log10()).max(MIN_LEVEL_DB)
+}
+
+// Notes held on the MIDI channel that drives one output. The output is monophonic:
+// the latest note sounds, and releasing it falls back to the highest note still held.
+// A channel with no notes held is muted.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MidiVoice {
+    held: u128,
+    note: Option<u8>,
+}
+
+impl MidiVoice {
+    // Applies a MIDI message to the settings of the output, returns whether they
+    // changed. Messages other than notes and the controllers above are ignored.
+    pub fn handle(&mut self, bytes: &[u8], params: &mut ChannelParams) -> bool {
+        let old = *params;
+        match *bytes {
+            [status, note, velocity] if status & 0xf0 == 0x90 && velocity > 0 => {
+                let note = note & 0x7f;
+                self.held |= 1 << note;
+                self.note = Some(note);
+                params.freq = note_hz(note);
+                params.muted = false;
+            }
+            // a note on with no velocity is a note off
+            [status, note, _] if status & 0xf0 == 0x80 || status & 0xf0 == 0x90 => {
+                let note = note & 0x7f;
+                self.held &= !(1 << note);
+                if self.note == Some(note) {
+                    if self.held == 0 {
+                        self.note = None;
+                        params.muted = true;
+                    } else {
+                        let top = 127 - self.held.leading_zeros() as u8;
+                        self.note = Some(top);
+                        params.freq = note_hz(top);
+                    }
+                }
+            }
+            [status, CC_VOLUME, value] if status & 0xf0 == 0xb0 => {
+                params.level_db = volume_db(value & 0x7f);
+            }
+            [status, CC_WAVEFORM, value] if status & 0xf0 == 0xb0 => {
+                params.waveform = WAVEFORMS[(value & 0x7f) as usize * WAVEFORMS.len() / 128];
+            }
+            [status, CC_ALL_NOTES_OFF, _] if status & 0xf0 == 0xb0 => {
+                self.held = 0;
+                self.note = None;
+                params.muted = true;
+            }
+            _ => {}
+        }
+        *params != old
+    }
+}
+
+// One output channel of the generator. Everything is preallocated, next() is safe to
+// call from the Jack process callback. The phase is an f64 accumulator kept between
+// 0 and 1, so it holds its precision however long the generator runs.
+pub struct Oscillator {
+    params: ChannelParams,
+    sample_rate: f64,
+    // per sample coefficient of the glides
+    glide: f64,
+    target_gain: f64,
+    // where the glides are at, they chase the settings
+    gain: f64,
+    freq: f64,
+    // in cycles
+    offset: f64,
+    // position in the period, 0 to 1
+    phase: f64,
+    // phase of the upper IMD tone
+    phase2: f64,
+    rng: u32,
+    pink: [f32; 7],
+    brown: f32,
+    brown_leak: f32,
+    lfsr: u32,
+    // cycles since the current burst started, and whether this sample started it
+    burst_cycle: u32,
+    burst_start: bool,
+    // seconds into the current pass of a sweep, and whether it runs from stop to start
+    sweep_time: f64,
+    sweep_down: bool,
+    pass: Pass,
+}
+
+impl Oscillator {
+    pub fn new(params: ChannelParams, sample_rate: usize, seed: u32) -> Oscillator {
+        let mut osc = Oscillator {
+            params,
+            sample_rate: 0.0,
+            glide: 0.0,
+            target_gain: params.gain() as f64,
+            gain: params.gain() as f64,
+            freq: params.freq as f64,
+            offset: params.phase_deg as f64 / 360.0,
+            phase: 0.0,
+            phase2: 0.0,
+            rng: seed.max(1),
+            pink: [0.0; 7],
+            brown: 0.0,
+            brown_leak: 0.0,
+            lfsr: 1,
+            sweep_time: 0.0,
+            sweep_down: false,
+            pass: Pass::Running,
+            burst_cycle: 0,
+            burst_start: false,
+        };
+        osc.set_sample_rate(sample_rate);
+        osc
+    }
+
+    // Keeps the frequencies and glide times when Jack changes its sample rate.
+    pub fn set_sample_rate(&mut self, sample_rate: usize) {
+        let sr = sample_rate as f64;
+        self.sample_rate = sr;
+        self.glide = 1.0 - (-1.0 / (GLIDE_SECS * sr)).exp();
+        self.brown_leak = (-2.0 * PI * BROWN_CORNER_HZ / sr).exp() as f32;
+    }
+
+    pub fn params(&self) -> ChannelParams {
+        self.params
+    }
+
+    pub fn set_params(&mut self, params: ChannelParams) {
+        let old = self.params;
+        self.params = params;
+        self.target_gain = params.gain() as f64;
+        if params.waveform == Waveform::Sweep
+            && (old.waveform != Waveform::Sweep || old.sweep != params.sweep)
+        {
+            self.restart();
+        }
+        // the new bursts start with the next cycle
+        if params.burst != old.burst {
+            self.burst_cycle = params.burst.on_cycles.max(1) + params.burst.off_cycles - 1;
+        }
+    }
+
+    fn bursting(&self) -> bool {
+        self.params.burst.enabled && self.params.waveform.is_periodic()
+    }
+
+    // Whether the last sample out of next() was the first of a burst.
+    pub fn burst_started(&self) -> bool {
+        self.burst_start
+    }
+
+    // Gain of the burst envelope at the current phase.
+    fn burst_envelope(&self) -> f64 {
+        let burst = self.params.burst;
+        let on = burst.on_cycles.max(1);
+        if self.burst_cycle >= on {
+            0.0
+        } else if burst.shaped {
+            let x = (self.burst_cycle as f64 + self.phase) / on as f64;
+            0.5 - 0.5 * (2.0 * PI * x).cos()
+        } else {
+            1.0
+        }
+    }
+
+    // Starts a sweep over from the start frequency.
+    pub fn restart(&mut self) {
+        self.sweep_time = 0.0;
+        self.sweep_down = false;
+        self.pass = Pass::Running;
+        self.phase = 0.0;
+    }
+
+    // Instantaneous frequency of the sweep, moving it on by a sample. The phase is
+    // accumulated from this, so it stays continuous through the turnarounds.
+    fn sweep_freq(&mut self) -> f64 {
+        let sweep = self.params.sweep;
+        let duration = (sweep.duration as f64).max(0.01);
+        if self.pass == Pass::Running && self.sweep_time >= duration {
+            match sweep.repeat {
+                SweepRepeat::Once => self.pass = Pass::LastCycle,
+                SweepRepeat::Repeat => self.sweep_time -= duration,
+                SweepRepeat::PingPong => {
+                    self.sweep_time -= duration;
+                    self.sweep_down = !self.sweep_down;
+                }
+            }
+        }
+        let x = (self.sweep_time / duration).min(1.0);
+        self.sweep_time += 1.0 / self.sample_rate;
+        sweep.hz(if self.sweep_down { 1.0 - x } else { x })
+    }
+
+    // Uniform white noise with unit RMS, from a xorshift generator.
+    fn white(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        let uniform = self.rng as f32 / u32::MAX as f32 * 2.0 - 1.0;
+        uniform * 3f32.sqrt()
+    }
+
+    // Paul Kellet's refined pink noise filter.
+    fn pink(&mut self) -> f32 {
+        let w = self.white();
+        let b = &mut self.pink;
+        b[0] = 0.99886 * b[0] + w * 0.0555179;
+        b[1] = 0.99332 * b[1] + w * 0.0750759;
+        b[2] = 0.96900 * b[2] + w * 0.1538520;
+        b[3] = 0.86650 * b[3] + w * 0.3104856;
+        b[4] = 0.55000 * b[4] + w * 0.5329522;
+        b[5] = -0.7616 * b[5] - w * 0.0168980;
+        let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + w * 0.5362;
+        b[6] = w * 0.115926;
+        pink / PINK_RMS
+    }
+
+    // Leaky integration keeps the RMS at one, a pure integrator would wander off.
+    fn brown(&mut self) -> f32 {
+        let w = self.white();
+        let leak = self.brown_leak;
+        self.brown = leak * self.brown + (1.0 - leak * leak).sqrt() * w;
+        self.brown
+    }
+
+    fn mls(&mut self) -> f32 {
+        let order = self.params.mls_order.clamp(MIN_MLS_ORDER, MAX_MLS_ORDER);
+        let mask = MLS_TAPS[(order - MIN_MLS_ORDER) as usize];
+        // a shorter sequence may leave the state outside its register
+        self.lfsr &= (1 << order) - 1;
+        if self.lfsr == 0 {
+            self.lfsr = 1;
+        }
+        let bit = self.lfsr & 1;
+        self.lfsr >>= 1;
+        if bit == 1 {
+            self.lfsr ^= mask;
+        }
+        if bit == 1 {
+            1.0
+        } else {
+            -1.0
+        }
+    }
+
+    pub fn next(&mut self) -> f32 {
+        let glide = self.glide;
+        self.gain += (self.target_gain - self.gain) * glide;
+        self.freq += (self.params.freq as f64 - self.freq) * glide;
+        // the offset takes the short way round
+        let to = self.params.phase_deg as f64 / 360.0 - self.offset;
+        self.offset = (self.offset + ((to + 0.5).rem_euclid(1.0) - 0.5) * glide).rem_euclid(1.0);
+
+        let freq = match self.params.waveform {
+            Waveform::Sweep => self.sweep_freq(),
+            Waveform::Imd(standard) => standard.tones().0 as f64,
+            _ => self.freq,
+        };
+        let dt = (freq / self.sample_rate).clamp(0.0, 0.5);
+        let t = (self.phase + self.offset) % 1.0;
+        let y = match self.params.waveform {
+            Waveform::Off => 0.0,
+            Waveform::Sine => (2.0 * PI * t).sin() as f32,
+            Waveform::White => self.white(),
+            Waveform::Pink => self.pink(),
+            Waveform::Brown => self.brown(),
+            Waveform::Square => {
+                let naive = if t < 0.5 { 1.0 } else { -1.0 };
+                (naive + poly_blep(t, dt) - poly_blep((t + 0.5) % 1.0, dt)) as f32
+            }
+            Waveform::Saw => (2.0 * t - 1.0 - poly_blep(t, dt)) as f32,
+            Waveform::Triangle => {
+                let naive = 4.0 * (t - 0.5).abs() - 1.0;
+                (naive - 4.0 * dt * (poly_blamp(t, dt) - poly_blamp((t + 0.5) % 1.0, dt))) as f32
+            }
+            Waveform::Mls => self.mls(),
+            Waveform::Dc => 1.0,
+            Waveform::Sweep => match self.pass {
+                Pass::Done => 0.0,
+                _ => (2.0 * PI * t).sin() as f32,
+            },
+            Waveform::Imd(standard) => {
+                let (_, f2, _) = standard.tones();
+                let (a1, a2) = imd_amplitudes(standard);
+                let y = a1 * (2.0 * PI * t).sin() as f32
+                    + a2 * (2.0 * PI * (self.phase2 + self.offset)).sin() as f32;
+                self.phase2 = (self.phase2 + f2 as f64 / self.sample_rate) % 1.0;
+                y
+            }
+        };
+        let envelope = if self.bursting() {
+            self.burst_envelope()
+        } else {
+            1.0
+        };
+        self.burst_start = self.bursting() && self.burst_cycle == 0 && self.phase < dt;
+
+        let next = self.phase + dt;
+        if next >= 1.0 {
+            let burst = self.params.burst;
+            let period = burst.on_cycles.max(1) + burst.off_cycles;
+            self.burst_cycle = (self.burst_cycle + 1) % period;
+        }
+        if self.pass == Pass::LastCycle && next >= 1.0 {
+            self.pass = Pass::Done;
+            self.phase = 0.0;
+        } else {
+            self.phase = next % 1.0;
+        }
+        y * (self.gain * envelope) as f32
+    }
+}
+
+pub struct GeneratorView {
+    channels: Vec<ChannelParams>,
+}
+
+// What the generator starts out with, a harmonic series of sines from 220 Hz. They
+// start muted so nothing plays before the user asks for it.
+pub fn default_channels(channels: usize) -> Vec<ChannelParams> {
+    (1..=channels)
+        .map(|n| ChannelParams {
+            muted: true,
+            ..ChannelParams::sine(220.0 * n as f32)
+        })
+        .collect()
+}
+
+impl GeneratorView {
+    pub fn new(channels: usize) -> GeneratorView {
+        GeneratorView {
+            channels: default_channels(channels),
+        }
+    }
+
+    // Follows a change the generator made on its own, from MIDI.
+    pub fn set_channel(&mut self, channel: usize, params: ChannelParams) {
+        if let Some(p) = self.channels.get_mut(channel) {
+            *p = params;
+        }
+    }
+
+    // Returns the commands for the generator, one per changed channel.
+    pub fn controls_ui(&mut self, ui: &mut egui::Ui, sample_rate: usize) -> Vec<GenCommand> {
+        let nyquist = sample_rate as f32 / 2.0;
+        let mut commands = vec![];
+        ui.label("MIDI channel n plays out n: notes gate, CC 7 level, CC 70 waveform");
+        for (c, params) in self.channels.iter_mut().enumerate() {
+            let old = *params;
+            ui.horizontal(|ui| {
+                ui.colored_label(channel_color(c), format!("out {}", c + 1));
+                ui.checkbox(&mut params.muted, "mute");
+            });
+            egui::ComboBox::from_id_source(("waveform", c))
+                .selected_text(params.waveform.name())
+                .show_ui(ui, |ui| {
+                    for w in WAVEFORMS.iter() {
+                        ui.selectable_value(&mut params.waveform, *w, w.name());
+                    }
+                });
+            if params.waveform.is_periodic() {
+                ui.add(
+                    egui::Slider::new(&mut params.freq, 1.0..=nyquist)
+                        .logarithmic(true)
+                        .text("Hz"),
+                );
+            }
+            if params.waveform == Waveform::Mls {
+                ui.add(
+                    egui::Slider::new(&mut params.mls_order, MIN_MLS_ORDER..=MAX_MLS_ORDER)
+                        .text("order"),
+                );
+                ui.label(format!("period {} samples", (1u32 << params.mls_order) - 1));
+            }
+            if params.waveform == Waveform::Sweep {
+                let sweep = &mut params.sweep;
+                ui.horizontal(|ui| {
+                    ui.radio_value(&mut sweep.law, SweepLaw::Linear, "linear");
+                    ui.radio_value(&mut sweep.law, SweepLaw::Log, "log");
+                });
+                ui.add(
+                    egui::Slider::new(&mut sweep.start_hz, 1.0..=nyquist)
+                        .logarithmic(true)
+                        .text("start Hz"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut sweep.stop_hz, 1.0..=nyquist)
+                        .logarithmic(true)
+                        .text("stop Hz"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut sweep.duration, 0.1..=120.0)
+                        .logarithmic(true)
+                        .text("seconds"),
+                );
+                ui.horizontal(|ui| {
+                    ui.radio_value(&mut sweep.repeat, SweepRepeat::Once, "once");
+                    ui.radio_value(&mut sweep.repeat, SweepRepeat::Repeat, "repeat");
+                    ui.radio_value(&mut sweep.repeat, SweepRepeat::PingPong, "ping-pong");
+                    if ui.button("restart").clicked() {
+                        commands.push(GenCommand::RestartSweep(c));
+                    }
+                });
+            }
+            ui.add(egui::Slider::new(&mut params.level_db, MIN_LEVEL_DB..=0.0).text("dBFS"));
+            if params.waveform.is_periodic() || params.waveform == Waveform::Sweep {
+                ui.add(egui::Slider::new(&mut params.phase_deg, -180.0..=180.0).text("phase °"));
+            }
+            if params.waveform.is_periodic() {
+                let burst = &mut params.burst;
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut burst.enabled, "burst");
+                    ui.checkbox(&mut burst.shaped, "raised cosine");
+                });
+                if burst.enabled {
+                    ui.add(egui::Slider::new(&mut burst.on_cycles, 1..=1000).text("cycles on"));
+                    ui.add(egui::Slider::new(&mut burst.off_cycles, 0..=1000).text("cycles off"));
+                }
+            }
+            if *params != old {
+                commands.push(GenCommand::Channel(c, *params));
+            }
+        }
+        commands
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(voice: &mut MidiVoice, params: &mut ChannelParams, messages: &[[u8; 3]]) {
+        for m in messages {
+            voice.handle(m, params);
+        }
+    }
+
+    #[test]
+    fn note_on_without_velocity_releases_the_note() {
+        let mut voice = MidiVoice::default();
+        let mut params = ChannelParams::sine(220.0);
+        play(&mut voice, &mut params, &[[0x90, 60, 100]]);
+        assert!(!params.muted);
+        assert_eq!(params.freq, note_hz(60));
+
+        assert!(voice.handle(&[0x90, 60, 0], &mut params));
+        assert!(params.muted);
+    }
+
+    #[test]
+    fn release_falls_back_to_the_highest_held_note() {
+        let mut voice = MidiVoice::default();
+        let mut params = ChannelParams::sine(220.0);
+        play(
+            &mut voice,
+            &mut params,
+            &[[0x90, 60, 100], [0x90, 67, 100], [0x90, 64, 100]],
+        );
+        assert_eq!(params.freq, note_hz(64));
+
+        play(&mut voice, &mut params, &[[0x80, 64, 0]]);
+        assert_eq!(params.freq, note_hz(67));
+        // releasing a note that is not sounding changes nothing
+        assert!(!voice.handle(&[0x80, 60, 0], &mut params));
+        assert_eq!(params.freq, note_hz(67));
+
+        play(&mut voice, &mut params, &[[0x80, 67, 0]]);
+        assert!(params.muted);
+    }
+
+    #[test]
+    fn out_of_range_controller_values_are_masked() {
+        let mut voice = MidiVoice::default();
+        let mut params = ChannelParams::sine(220.0);
+        play(
+            &mut voice,
+            &mut params,
+            &[[0xb0, CC_VOLUME, 0xff], [0xb0, CC_WAVEFORM, 0xff]],
+        );
+        assert_eq!(params.level_db, volume_db(0x7f));
+        assert!(params.level_db <= 0.0);
+        assert_eq!(params.waveform, WAVEFORMS[WAVEFORMS.len() - 1]);
+    }
+
+    #[test]
+    fn all_notes_off_mutes_and_forgets_held_notes() {
+        let mut voice = MidiVoice::default();
+        let mut params = ChannelParams::sine(220.0);
+        play(&mut voice, &mut params, &[[0x90, 60, 100], [0x90, 64, 100]]);
+
+        assert!(voice.handle(&[0xb0, CC_ALL_NOTES_OFF, 0], &mut params));
+        assert!(params.muted);
+        assert!(!voice.handle(&[0x80, 64, 0], &mut params));
+
+        play(&mut voice, &mut params, &[[0x90, 62, 100], [0x80, 62, 0]]);
+        assert!(params.muted);
+    }
+}
@@ -0,0 +1,342 @@
+use super::common::*;
+use egui::widgets::plot::{Curve, Plot, Value};
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+const HISTORY_SECS: f32 = 10.0;
+const MAX_LOG: usize = 200;
+// A tone group's strongest tone must beat the runner up by this much
+const DOMINANCE_DB: f32 = 6.0;
+// Largest level difference between the two tones of a digit
+const MAX_TWIST_DB: f32 = 8.0;
+
+const DTMF_LOW: [f32; 4] = [697.0, 770.0, 852.0, 941.0];
+const DTMF_HIGH: [f32; 4] = [1209.0, 1336.0, 1477.0, 1633.0];
+const DTMF_KEYS: [[char; 4]; 4] = [
+    ['1', '2', '3', 'A'],
+    ['4', '5', '6', 'B'],
+    ['7', '8', '9', 'C'],
+    ['*', '0', '#', 'D'],
+];
+
+// R1 multi-frequency signalling, two out of six tones
+const MF_TONES: [f32; 6] = [700.0, 900.0, 1100.0, 1300.0, 1500.0, 1700.0];
+// Digit of every pair of MF tones, by index into MF_TONES. KP is written K and the
+// ST variants S, P, Q and R.
+const MF_KEYS: [(usize, usize, char); 15] = [
+    (0, 1, '1'),
+    (0, 2, '2'),
+    (1, 2, '3'),
+    (0, 3, '4'),
+    (1, 3, '5'),
+    (2, 3, '6'),
+    (0, 4, '7'),
+    (1, 4, '8'),
+    (2, 4, '9'),
+    (3, 4, '0'),
+    (2, 5, 'K'),
+    (4, 5, 'S'),
+    (1, 5, 'P'),
+    (3, 5, 'Q'),
+    (0, 5, 'R'),
+];
+
+fn db(amplitude: f32) -> f32 {
+    20.0 * amplitude.max(1e-10).log10()
+}
+
+// Single frequency DFT over a block. The frequency does not have to fall on a bin.
+#[derive(Debug, Clone, Copy)]
+pub struct Goertzel {
+    pub hz: f32,
+    coeff: f64,
+    cos: f64,
+    sin: f64,
+}
+
+impl Goertzel {
+    pub fn new(hz: f32, sample_rate: usize) -> Goertzel {
+        let w = 2.0 * PI * hz as f64 / sample_rate as f64;
+        Goertzel {
+            hz,
+            coeff: 2.0 * w.cos(),
+            cos: w.cos(),
+            sin: w.sin(),
+        }
+    }
+
+    // Peak amplitude of the tone in the block, a full scale sine reads 1.
+    pub fn amplitude(&self, block: &[f32]) -> f32 {
+        let mut s1 = 0.0;
+        let mut s2 = 0.0;
+        for x in block {
+            let s = *x as f64 + self.coeff * s1 - s2;
+            s2 = s1;
+            s1 = s;
+        }
+        let re = s1 - s2 * self.cos;
+        let im = s2 * self.sin;
+        (2.0 * (re * re + im * im).sqrt() / block.len() as f64) as f32
+    }
+}
+
+// Index of the strongest level if it is above the threshold and dominates the rest.
+fn dominant(levels: &[f32], threshold_db: f32) -> Option<usize> {
+    let mut order: Vec<usize> = (0..levels.len()).collect();
+    order.sort_by(|a, b| levels[*b].partial_cmp(&levels[*a]).unwrap());
+    let best = order[0];
+    if levels[best] < threshold_db || levels[best] - levels[order[1]] < DOMINANCE_DB {
+        return None;
+    }
+    Some(best)
+}
+
+// DTMF digit from the levels in dBFS of DTMF_LOW followed by DTMF_HIGH.
+pub fn dtmf_digit(levels: &[f32], threshold_db: f32) -> Option<char> {
+    let row = dominant(&levels[0..4], threshold_db)?;
+    let col = dominant(&levels[4..8], threshold_db)?;
+    if (levels[row] - levels[4 + col]).abs() > MAX_TWIST_DB {
+        return None;
+    }
+    Some(DTMF_KEYS[row][col])
+}
+
+// MF digit from the levels in dBFS of MF_TONES.
+pub fn mf_digit(levels: &[f32], threshold_db: f32) -> Option<char> {
+    let mut order: Vec<usize> = (0..levels.len()).collect();
+    order.sort_by(|a, b| levels[*b].partial_cmp(&levels[*a]).unwrap());
+    let (a, b, c) = (order[0], order[1], order[2]);
+    if levels[b] < threshold_db
+        || levels[a] - levels[b] > MAX_TWIST_DB
+        || levels[b] - levels[c] < DOMINANCE_DB
+    {
+        return None;
+    }
+    let (lo, hi) = (a.min(b), a.max(b));
+    MF_KEYS
+        .iter()
+        .find(|(x, y, _)| *x == lo && *y == hi)
+        .map(|(_, _, key)| *key)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Decoder {
+    Off,
+    Dtmf,
+    Mf,
+}
+
+pub struct GoertzelView {
+    pub open: bool,
+    channel: usize,
+    block_ms: f32,
+    threshold_db: f32,
+    decoder: Decoder,
+    tones: Vec<f32>,
+    new_tone: f32,
+    sample_rate: usize,
+    detectors: Vec<Goertzel>,
+    decoders: Vec<Goertzel>,
+    pending: Vec<f32>,
+    // samples analyzed so far, the time base of the log
+    position: u64,
+    levels: Vec<VecDeque<f32>>,
+    // a digit is accepted once it shows up in two blocks in a row
+    candidate: Option<char>,
+    current: Option<char>,
+    log: VecDeque<(f32, char)>,
+}
+
+impl Default for GoertzelView {
+    fn default() -> Self {
+        Self {
+            open: false,
+            channel: 0,
+            block_ms: 20.0,
+            threshold_db: -30.0,
+            decoder: Decoder::Off,
+            tones: vec![1000.0],
+            new_tone: 440.0,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            detectors: vec![],
+            decoders: vec![],
+            pending: vec![],
+            position: 0,
+            levels: vec![],
+            candidate: None,
+            current: None,
+            log: VecDeque::new(),
+        }
+    }
+}
+
+impl GoertzelView {
+    fn block_len(&self) -> usize {
+        ((self.block_ms / 1000.0 * self.sample_rate as f32) as usize).max(1)
+    }
+
+    fn history_len(&self) -> usize {
+        (HISTORY_SECS * 1000.0 / self.block_ms) as usize
+    }
+
+    // Rebuilds the detectors after the tones, decoder or sample rate changed.
+    fn rebuild(&mut self) {
+        let sr = self.sample_rate;
+        self.detectors = self.tones.iter().map(|hz| Goertzel::new(*hz, sr)).collect();
+        let tones: Vec<f32> = match self.decoder {
+            Decoder::Off => vec![],
+            Decoder::Dtmf => DTMF_LOW.iter().chain(DTMF_HIGH.iter()).cloned().collect(),
+            Decoder::Mf => MF_TONES.to_vec(),
+        };
+        self.decoders = tones.iter().map(|hz| Goertzel::new(*hz, sr)).collect();
+        self.levels = vec![VecDeque::new(); self.tones.len()];
+        self.candidate = None;
+        self.current = None;
+    }
+
+    pub fn push(&mut self, block: &[Vec<f32>], sample_rate: usize) {
+        if self.sample_rate != sample_rate || self.detectors.len() != self.tones.len() {
+            self.sample_rate = sample_rate;
+            self.rebuild();
+        }
+        self.pending
+            .extend_from_slice(&block[self.channel.min(block.len() - 1)]);
+
+        let len = self.block_len();
+        let history = self.history_len();
+        let mut start = 0;
+        while self.pending.len() - start >= len {
+            let samples = &self.pending[start..start + len];
+            for (d, levels) in self.detectors.iter().zip(self.levels.iter_mut()) {
+                if levels.len() >= history {
+                    levels.pop_front();
+                }
+                levels.push_back(db(d.amplitude(samples)));
+            }
+
+            let decoded: Vec<f32> = self
+                .decoders
+                .iter()
+                .map(|d| db(d.amplitude(samples)))
+                .collect();
+            let digit = match self.decoder {
+                Decoder::Off => None,
+                Decoder::Dtmf => dtmf_digit(&decoded, self.threshold_db),
+                Decoder::Mf => mf_digit(&decoded, self.threshold_db),
+            };
+            if digit.is_some() && digit == self.candidate && digit != self.current {
+                let secs = self.position as f32 / self.sample_rate as f32;
+                if self.log.len() == MAX_LOG {
+                    self.log.pop_front();
+                }
+                self.log.push_back((secs, digit.unwrap()));
+                self.current = digit;
+            }
+            if digit.is_none() {
+                self.current = None;
+            }
+            self.candidate = digit;
+
+            self.position += len as u64;
+            start += len;
+        }
+        self.pending.drain(0..start);
+    }
+
+    pub fn controls_ui(&mut self, ui: &mut egui::Ui, channels: usize) {
+        ui.checkbox(&mut self.open, "show");
+        channel_combo(ui, "channel", &mut self.channel, channels);
+
+        let old = (self.block_ms, self.decoder, self.tones.clone());
+        ui.add(egui::Slider::new(&mut self.block_ms, 5.0..=200.0).text("block ms"));
+        ui.label(format!("resolution {:.1} Hz", 1000.0 / self.block_ms));
+
+        let nyquist = self.sample_rate as f32 / 2.0;
+        let mut remove = None;
+        for (i, hz) in self.tones.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::DragValue::new(hz)
+                        .speed(1.0)
+                        .clamp_range(1.0..=nyquist)
+                        .suffix(" Hz"),
+                );
+                if ui.button("remove").clicked() {
+                    remove = Some(i);
+                }
+            });
+        }
+        if let Some(i) = remove {
+            self.tones.remove(i);
+        }
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::DragValue::new(&mut self.new_tone)
+                    .speed(1.0)
+                    .clamp_range(1.0..=nyquist)
+                    .suffix(" Hz"),
+            );
+            if ui.button("add").clicked() {
+                self.tones.push(self.new_tone);
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("decoder");
+            ui.radio_value(&mut self.decoder, Decoder::Off, "off");
+            ui.radio_value(&mut self.decoder, Decoder::Dtmf, "DTMF");
+            ui.radio_value(&mut self.decoder, Decoder::Mf, "MF");
+        });
+        ui.add(egui::Slider::new(&mut self.threshold_db, -80.0..=0.0).text("threshold dBFS"));
+        if ui.button("clear log").clicked() {
+            self.log.clear();
+        }
+
+        if old != (self.block_ms, self.decoder, self.tones.clone()) {
+            self.rebuild();
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        for (hz, levels) in self.tones.iter().zip(self.levels.iter()) {
+            if let Some(level) = levels.back() {
+                ui.label(format!("{} Hz: {:.1} dBFS", hz, level));
+            }
+        }
+
+        let secs = self.block_ms as f64 / 1000.0;
+        let plot = self.tones.iter().zip(self.levels.iter()).enumerate().fold(
+            Plot::new("tone levels"),
+            |plot, (i, (hz, levels))| {
+                let values = levels
+                    .iter()
+                    .enumerate()
+                    .map(|(n, l)| Value::new(n as f64 * secs, *l as f64))
+                    .collect();
+                plot.curve(
+                    Curve::from_values(values)
+                        .color(channel_color(i))
+                        .name(format!("{} Hz", hz)),
+                )
+            },
+        );
+        ui.add(
+            plot.include_y(0.0)
+                .include_y(-100.0)
+                .allow_drag(false)
+                .height(160.0),
+        );
+
+        if self.decoder != Decoder::Off {
+            let digits: String = self.log.iter().map(|(_, d)| *d).collect();
+            ui.label(format!("decoded: {}", digits));
+            egui::ScrollArea::vertical()
+                .max_height(120.0)
+                .show(ui, |ui| {
+                    for (secs, digit) in self.log.iter().rev() {
+                        ui.label(format!("{:10.3} s  {}", secs, digit));
+                    }
+                });
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use super::common::*;
+use egui::widgets::plot::{Curve, Plot, Value};
+use std::collections::VecDeque;
+use std::f32::consts::FRAC_1_SQRT_2;
+
+// Points drawn per block, longer blocks are thinned out
+const MAX_POINTS: usize = 4096;
+// Length of each correlation measurement
+const CORRELATION_WINDOW_SECS: f32 = 0.05;
+const HISTORY_SECS: f32 = 30.0;
+
+// Pearson correlation of two equally long signals, 0 when either is silent.
+pub fn correlation(left: &[f32], right: &[f32]) -> f32 {
+    let mut lr = 0.0;
+    let mut ll = 0.0;
+    let mut rr = 0.0;
+    for (l, r) in left.iter().zip(right.iter()) {
+        lr += l * r;
+        ll += l * l;
+        rr += r * r;
+    }
+    if ll == 0.0 || rr == 0.0 {
+        return 0.0;
+    }
+    lr / (ll * rr).sqrt()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GonioMode {
+    // mid on the vertical axis, side on the horizontal, mono is a vertical line
+    Goniometer,
+    // left on the horizontal axis, right on the vertical
+    Lissajous,
+}
+
+pub struct GonioView {
+    pub open: bool,
+    mode: GonioMode,
+    left: usize,
+    right: usize,
+    gain: f32,
+    points: Vec<(f32, f32)>,
+    correlation: f32,
+    history: VecDeque<f32>,
+    // samples short of a full correlation window, carried over to the next block
+    pending_left: Vec<f32>,
+    pending_right: Vec<f32>,
+}
+
+impl Default for GonioView {
+    fn default() -> Self {
+        Self {
+            open: false,
+            mode: GonioMode::Goniometer,
+            left: 0,
+            right: 1,
+            gain: 1.0,
+            points: vec![],
+            correlation: 0.0,
+            history: VecDeque::new(),
+            pending_left: vec![],
+            pending_right: vec![],
+        }
+    }
+}
+
+impl GonioView {
+    fn history_len(&self) -> usize {
+        (HISTORY_SECS / CORRELATION_WINDOW_SECS) as usize
+    }
+
+    pub fn push(&mut self, block: &[Vec<f32>], sample_rate: usize) {
+        let last = block.len() - 1;
+        let left = &block[self.left.min(last)];
+        let right = &block[self.right.min(last)];
+
+        let step = (left.len() / MAX_POINTS).max(1);
+        self.points = left
+            .iter()
+            .zip(right.iter())
+            .step_by(step)
+            .map(|(l, r)| (*l, *r))
+            .collect();
+
+        let window = ((CORRELATION_WINDOW_SECS * sample_rate as f32) as usize).max(1);
+        self.pending_left.extend_from_slice(left);
+        self.pending_right.extend_from_slice(right);
+        while self.pending_left.len() >= window {
+            if self.history.len() == self.history_len() {
+                self.history.pop_front();
+            }
+            let r = correlation(&self.pending_left[..window], &self.pending_right[..window]);
+            self.history.push_back(r);
+            self.pending_left.drain(0..window);
+            self.pending_right.drain(0..window);
+        }
+        self.correlation = correlation(left, right);
+    }
+
+    pub fn controls_ui(&mut self, ui: &mut egui::Ui, channels: usize) {
+        ui.checkbox(&mut self.open, "show");
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut self.mode, GonioMode::Goniometer, "goniometer");
+            ui.radio_value(&mut self.mode, GonioMode::Lissajous, "lissajous");
+        });
+        channel_combo(ui, "left", &mut self.left, channels);
+        channel_combo(ui, "right", &mut self.right, channels);
+        ui.add(
+            egui::Slider::new(&mut self.gain, 0.1..=20.0)
+                .logarithmic(true)
+                .text("gain"),
+        );
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        let side = ui.available_width();
+        let (response, painter) = ui.allocate_painter(egui::vec2(side, side), egui::Sense::hover());
+        let rect = response.rect;
+        let center = rect.center();
+        let radius = side / 2.0;
+
+        painter.rect_filled(rect, 0.0, egui::Color32::from_gray(10));
+        let grid = egui::Stroke::new(1.0, egui::Color32::from_gray(50));
+        painter.line_segment([rect.left_top(), rect.right_bottom()], grid);
+        painter.line_segment([rect.right_top(), rect.left_bottom()], grid);
+        painter.line_segment(
+            [
+                egui::pos2(center.x, rect.top()),
+                egui::pos2(center.x, rect.bottom()),
+            ],
+            grid,
+        );
+        painter.line_segment(
+            [
+                egui::pos2(rect.left(), center.y),
+                egui::pos2(rect.right(), center.y),
+            ],
+            grid,
+        );
+
+        let dot = egui::Color32::from_rgba_unmultiplied(120, 200, 120, 60);
+        for (l, r) in self.points.iter() {
+            let (x, y) = match self.mode {
+                GonioMode::Goniometer => ((r - l) * FRAC_1_SQRT_2, (l + r) * FRAC_1_SQRT_2),
+                GonioMode::Lissajous => (*l, *r),
+            };
+            let x = (x * self.gain).clamp(-1.0, 1.0);
+            let y = (y * self.gain).clamp(-1.0, 1.0);
+            painter.circle_filled(
+                egui::pos2(center.x + x * radius, center.y - y * radius),
+                1.0,
+                dot,
+            );
+        }
+
+        // correlation meter, -1 on the left to +1 on the right
+        let (response, painter) = ui.allocate_painter(egui::vec2(side, 16.0), egui::Sense::hover());
+        let bar = response.rect;
+        painter.rect_filled(bar, 0.0, egui::Color32::from_gray(30));
+        let x = bar.center().x + self.correlation.clamp(-1.0, 1.0) * bar.width() / 2.0;
+        let color = if self.correlation < 0.0 {
+            egui::Color32::from_rgb(220, 80, 80)
+        } else {
+            egui::Color32::from_rgb(120, 200, 120)
+        };
+        painter.rect_filled(
+            egui::Rect::from_min_max(
+                egui::pos2(bar.center().x.min(x), bar.top()),
+                egui::pos2(bar.center().x.max(x), bar.bottom()),
+            ),
+            0.0,
+            color,
+        );
+        ui.label(format!("correlation {:+.2}", self.correlation));
+
+        let secs = CORRELATION_WINDOW_SECS as f64;
+        let values = self
+            .history
+            .iter()
+            .enumerate()
+            .map(|(i, c)| Value::new(i as f64 * secs, *c as f64))
+            .collect();
+        ui.add(
+            Plot::new("correlation history")
+                .curve(Curve::from_values(values).color(color).name("s"))
+                .include_y(-1.0)
+                .include_y(1.0)
+                .allow_drag(false)
+                .height(100.0),
+        );
+    }
+}
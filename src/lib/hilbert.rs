@@ -0,0 +1,215 @@
+use super::common::*;
+use egui::widgets::plot::{Curve, Plot, Value};
+use num_complex::Complex32;
+use rustfft::FftPlanner;
+use std::f32::consts::PI;
+
+// Samples kept on each side of the shown span. The FFT treats the block as
+// periodic, which bends the analytic signal near both ends.
+const MARGIN: usize = 2048;
+const MAX_TIMEBASE_MS: f32 = 1000.0;
+// Plots never get more points than this, each point averages a chunk of samples.
+const MAX_PLOT_POINTS: usize = 1000;
+
+// Analytic signal through the FFT: the negative frequencies are dropped and the
+// positive ones doubled so the magnitude gives the envelope of x.
+pub fn analytic_signal(x: &[f32]) -> Vec<Complex32> {
+    let n = x.len();
+    let mut planner = FftPlanner::<f32>::new();
+    let fwd = planner.plan_fft_forward(n);
+    let inv = planner.plan_fft_inverse(n);
+
+    let mut buf: Vec<Complex32> = x.iter().map(|v| Complex32::new(*v, 0.0)).collect();
+    fwd.process(&mut buf);
+
+    for (i, v) in buf.iter_mut().enumerate() {
+        if i == 0 || (n % 2 == 0 && i == n / 2) {
+            continue;
+        }
+        if i < (n + 1) / 2 {
+            *v = *v * 2.0;
+        } else {
+            *v = Complex32::new(0.0, 0.0);
+        }
+    }
+
+    inv.process(&mut buf);
+    let scale = 1.0 / n as f32;
+    buf.iter_mut().for_each(|v| *v = *v * scale);
+    buf
+}
+
+// Instantaneous amplitude of x, and its instantaneous frequency in Hz from the
+// phase advance between neighbouring samples. The frequency has one value less.
+pub fn instantaneous(x: &[f32], sample_rate: usize) -> (Vec<f32>, Vec<f32>) {
+    let z = analytic_signal(x);
+    let amplitude = z.iter().map(|v| v.norm()).collect();
+    let hz_per_radian = sample_rate as f32 / (2.0 * PI);
+    let frequency = z
+        .windows(2)
+        .map(|w| (w[1] * w[0].conj()).arg() * hz_per_radian)
+        .collect();
+    (amplitude, frequency)
+}
+
+pub struct HilbertView {
+    pub open: bool,
+    channel: usize,
+    timebase_ms: f32,
+    // the frequency is not plotted where the amplitude is below this
+    gate_db: f32,
+    sample_rate: usize,
+    history: Vec<f32>,
+    dirty: bool,
+    // (seconds, value) of the shown span
+    amplitude: Vec<(f32, f32)>,
+    frequency: Vec<(f32, f32)>,
+}
+
+impl Default for HilbertView {
+    fn default() -> Self {
+        Self {
+            open: false,
+            channel: 0,
+            timebase_ms: 50.0,
+            gate_db: -60.0,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            history: vec![],
+            dirty: false,
+            amplitude: vec![],
+            frequency: vec![],
+        }
+    }
+}
+
+impl HilbertView {
+    fn span(&self) -> usize {
+        ((self.timebase_ms / 1000.0 * self.sample_rate as f32) as usize).max(2)
+    }
+
+    pub fn push(&mut self, block: &[Vec<f32>], sample_rate: usize) {
+        if self.sample_rate != sample_rate {
+            self.history.clear();
+            self.sample_rate = sample_rate;
+        }
+        self.history
+            .extend_from_slice(&block[self.channel.min(block.len() - 1)]);
+        let keep = self.span() + 2 * MARGIN;
+        if self.history.len() > keep {
+            let drop = self.history.len() - keep;
+            self.history.drain(0..drop);
+        }
+        self.dirty = true;
+    }
+
+    // Runs the transform over the history, at most once per repaint.
+    fn refresh(&mut self) {
+        let span = self.span();
+        if !self.dirty || self.history.len() < span + 2 * MARGIN {
+            return;
+        }
+        self.dirty = false;
+        let (amplitude, frequency) = instantaneous(&self.history, self.sample_rate);
+        let amplitude = &amplitude[MARGIN..MARGIN + span];
+        let frequency = &frequency[MARGIN..MARGIN + span];
+
+        let step = (span / MAX_PLOT_POINTS).max(1);
+        let secs = step as f32 / self.sample_rate as f32;
+        let gate = 10f32.powf(self.gate_db / 20.0);
+        let mean = |chunk: &[f32]| chunk.iter().sum::<f32>() / chunk.len() as f32;
+        self.amplitude = amplitude
+            .chunks(step)
+            .enumerate()
+            .map(|(n, chunk)| (n as f32 * secs, mean(chunk)))
+            .collect();
+        self.frequency = frequency
+            .chunks(step)
+            .zip(amplitude.chunks(step))
+            .enumerate()
+            .filter(|(_, (_, a))| mean(a) >= gate)
+            .map(|(n, (chunk, _))| (n as f32 * secs, mean(chunk)))
+            .collect();
+    }
+
+    pub fn controls_ui(&mut self, ui: &mut egui::Ui, channels: usize) {
+        ui.checkbox(&mut self.open, "show");
+        channel_combo(ui, "channel", &mut self.channel, channels);
+        let old = (self.channel, self.timebase_ms, self.gate_db);
+        ui.add(
+            egui::Slider::new(&mut self.timebase_ms, 1.0..=MAX_TIMEBASE_MS)
+                .logarithmic(true)
+                .text("span ms"),
+        );
+        ui.add(egui::Slider::new(&mut self.gate_db, -120.0..=0.0).text("frequency gate dBFS"));
+        if (self.channel, self.timebase_ms, self.gate_db) != old {
+            if self.channel != old.0 {
+                self.history.clear();
+            }
+            self.dirty = true;
+        }
+
+        // Modulation read off the shown span
+        let range = |values: &[(f32, f32)]| {
+            values
+                .iter()
+                .fold((f32::MAX, f32::MIN), |(lo, hi), (_, v)| {
+                    (lo.min(*v), hi.max(*v))
+                })
+        };
+        if !self.amplitude.is_empty() {
+            let (lo, hi) = range(&self.amplitude);
+            ui.label(format!(
+                "AM depth {:.1} %",
+                100.0 * (hi - lo) / (hi + lo).max(f32::MIN_POSITIVE)
+            ));
+        }
+        if !self.frequency.is_empty() {
+            let (lo, hi) = range(&self.frequency);
+            let mean =
+                self.frequency.iter().map(|(_, v)| v).sum::<f32>() / self.frequency.len() as f32;
+            ui.label(format!(
+                "mean {:.1} Hz, FM deviation {:.1} Hz",
+                mean,
+                (hi - lo) / 2.0
+            ));
+        }
+    }
+
+    // Amplitude above frequency, sharing the height.
+    pub fn ui(&mut self, ui: &mut egui::Ui, height: f32) {
+        self.refresh();
+        let values = |trace: &[(f32, f32)]| {
+            trace
+                .iter()
+                .map(|(t, v)| Value::new(*t as f64 * 1000.0, *v as f64))
+                .collect()
+        };
+        let color = channel_color(self.channel);
+        let span_ms = self.timebase_ms as f64;
+        ui.add(
+            Plot::new("instantaneous amplitude")
+                .curve(
+                    Curve::from_values(values(&self.amplitude))
+                        .color(color)
+                        .name("amplitude FS / ms"),
+                )
+                .include_x(0.0)
+                .include_x(span_ms)
+                .include_y(0.0)
+                .allow_drag(false)
+                .height(height / 2.0),
+        );
+        ui.add(
+            Plot::new("instantaneous frequency")
+                .curve(
+                    Curve::from_values(values(&self.frequency))
+                        .color(color)
+                        .name("frequency Hz / ms"),
+                )
+                .include_x(0.0)
+                .include_x(span_ms)
+                .allow_drag(false)
+                .height(height / 2.0),
+        );
+    }
+}
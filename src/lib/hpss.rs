@@ -0,0 +1,143 @@
+use super::common::*;
+use super::spectrum::{from_power, to_power};
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HpssMode {
+    Off,
+    Harmonic,
+    Percussive,
+    // harmonic above percussive
+    Both,
+}
+
+fn median(values: &mut [f32]) -> f32 {
+    let mid = values.len() / 2;
+    *values
+        .select_nth_unstable_by(mid, |a, b| a.partial_cmp(b).unwrap())
+        .1
+}
+
+// Median filter harmonic-percussive separation. Harmonic content is steady across
+// time, so a median along time keeps it and drops clicks; percussive content is
+// smooth across frequency, so a median along frequency keeps it and drops tones.
+// The two medians give soft masks that split each frame. The output lags the input
+// by half the time median.
+pub struct Hpss {
+    time_frames: usize,
+    freq_bins: usize,
+    history: VecDeque<Frame>,
+    powers: VecDeque<Vec<f32>>,
+}
+
+impl Hpss {
+    pub fn new(time_frames: usize, freq_bins: usize) -> Hpss {
+        Hpss {
+            time_frames: time_frames.max(1) | 1,
+            freq_bins: freq_bins.max(1) | 1,
+            history: VecDeque::new(),
+            powers: VecDeque::new(),
+        }
+    }
+
+    // The harmonic and percussive parts of the frame in the middle of the history,
+    // once there is enough history.
+    pub fn push(&mut self, frame: &Frame) -> Option<(Frame, Frame)> {
+        let compatible = self.history.back().map_or(true, |last: &Frame| {
+            last.axis == frame.axis
+                && last.scale == frame.scale
+                && last.bins.len() == frame.bins.len()
+        });
+        if !compatible {
+            self.history.clear();
+            self.powers.clear();
+        }
+        if self.history.len() == self.time_frames {
+            self.history.pop_front();
+            self.powers.pop_front();
+        }
+        self.history.push_back(frame.clone());
+        self.powers.push_back(
+            frame
+                .bins
+                .iter()
+                .map(|b| to_power(frame.scale, *b))
+                .collect(),
+        );
+        if self.history.len() < self.time_frames {
+            return None;
+        }
+
+        let mid = self.time_frames / 2;
+        let center = &self.powers[mid];
+        let len = center.len();
+        let half = self.freq_bins / 2;
+        let mut column = vec![0.0; self.time_frames];
+        let mut harmonic = Vec::with_capacity(len);
+        let mut percussive = Vec::with_capacity(len);
+        for k in 0..len {
+            for (c, p) in column.iter_mut().zip(self.powers.iter()) {
+                *c = p[k];
+            }
+            let h = median(&mut column);
+            let mut row = center[k.saturating_sub(half)..(k + half + 1).min(len)].to_vec();
+            let p = median(&mut row);
+
+            // Wiener style masks, the two parts add up to the input
+            let (h2, p2) = (h * h, p * p);
+            let total = (h2 + p2).max(1e-30);
+            harmonic.push(center[k] * h2 / total);
+            percussive.push(center[k] * p2 / total);
+        }
+
+        let source = &self.history[mid];
+        let scale = source.scale;
+        let split = |bins: Vec<f32>| Frame {
+            bins: bins.into_iter().map(|p| from_power(scale, p)).collect(),
+            ..source.clone()
+        };
+        Some((split(harmonic), split(percussive)))
+    }
+}
+
+pub struct HpssView {
+    pub mode: HpssMode,
+    time_frames: usize,
+    freq_bins: usize,
+    hpss: Hpss,
+}
+
+impl Default for HpssView {
+    fn default() -> Self {
+        Self {
+            mode: HpssMode::Off,
+            time_frames: 17,
+            freq_bins: 17,
+            hpss: Hpss::new(17, 17),
+        }
+    }
+}
+
+impl HpssView {
+    pub fn push(&mut self, frame: &Frame) -> Option<(Frame, Frame)> {
+        if self.mode == HpssMode::Off {
+            return None;
+        }
+        self.hpss.push(frame)
+    }
+
+    pub fn controls_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut self.mode, HpssMode::Off, "off");
+            ui.radio_value(&mut self.mode, HpssMode::Harmonic, "harmonic");
+            ui.radio_value(&mut self.mode, HpssMode::Percussive, "percussive");
+            ui.radio_value(&mut self.mode, HpssMode::Both, "both");
+        });
+        let old = (self.time_frames, self.freq_bins);
+        ui.add(egui::Slider::new(&mut self.time_frames, 3..=63).text("time median frames"));
+        ui.add(egui::Slider::new(&mut self.freq_bins, 3..=63).text("frequency median bins"));
+        if (self.time_frames, self.freq_bins) != old {
+            self.hpss = Hpss::new(self.time_frames, self.freq_bins);
+        }
+    }
+}
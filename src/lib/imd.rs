@@ -0,0 +1,260 @@
+use super::common::*;
+use super::psd::blackman_harris;
+use egui::widgets::plot::{Curve, Plot, Value};
+use realfft::{RealFftPlanner, RealToComplex};
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+// Bins on each side of a product whose power counts towards it, covers the main
+// lobe of the Blackman-Harris window.
+const SEARCH_BINS: usize = 4;
+// Sideband orders measured around the SMPTE carrier
+const SMPTE_ORDERS: usize = 4;
+const HISTORY: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImdStandard {
+    // 60 Hz and 7 kHz at 4:1, sidebands of the high tone (SMPTE RP120, DIN 45403)
+    Smpte,
+    // 19 and 20 kHz at 1:1, difference frequency products (CCIF, IEC 60268)
+    Ccif,
+}
+
+impl ImdStandard {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ImdStandard::Smpte => "SMPTE/DIN",
+            ImdStandard::Ccif => "CCIF",
+        }
+    }
+
+    // The two tones in Hz and the amplitude of the first relative to the second.
+    pub fn tones(&self) -> (f32, f32, f32) {
+        match self {
+            ImdStandard::Smpte => (60.0, 7000.0, 4.0),
+            ImdStandard::Ccif => (19000.0, 20000.0, 1.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ImdResult {
+    // Frequency and amplitude relative to the reference of each product
+    pub products: Vec<(f32, f32)>,
+    // Second and third order parts in percent, for CCIF
+    pub orders: Option<(f32, f32)>,
+    pub percent: f32,
+}
+
+// Amplitude of whatever the frame holds around hz, None when hz is off the frame.
+fn amplitude(frame: &Frame, hz: f32) -> Option<f32> {
+    let pos = frame.axis.bin(hz).round();
+    if pos < 0.0 || pos as usize >= frame.bins.len() {
+        return None;
+    }
+    let center = pos as usize;
+    let from = center.saturating_sub(SEARCH_BINS);
+    let to = (center + SEARCH_BINS).min(frame.bins.len() - 1);
+    Some((from..=to).map(|b| frame.bin_power(b)).sum::<f32>().sqrt())
+}
+
+// Measures the intermodulation products of the standard's tone pair in a spectrum.
+// Returns None when a product is off the frame or the bins are too wide to tell the
+// products apart.
+pub fn measure(frame: &Frame, standard: ImdStandard) -> Option<ImdResult> {
+    let (f1, f2, _) = standard.tones();
+    let spacing = match standard {
+        ImdStandard::Smpte => f1,
+        ImdStandard::Ccif => f2 - f1,
+    };
+    let bin = frame.axis.bin(f2).round().max(0.0) as usize;
+    if bin >= frame.bins.len() || frame.axis.width(bin) * (2 * SEARCH_BINS + 1) as f32 >= spacing {
+        return None;
+    }
+
+    match standard {
+        // Each pair of sidebands adds up, the orders add up as power
+        ImdStandard::Smpte => {
+            let carrier = amplitude(frame, f2)?.max(f32::MIN_POSITIVE);
+            let mut products = vec![];
+            let mut sum = 0.0;
+            for n in 1..=SMPTE_ORDERS {
+                let lo = f2 - n as f32 * f1;
+                let hi = f2 + n as f32 * f1;
+                let (a_lo, a_hi) = (amplitude(frame, lo)?, amplitude(frame, hi)?);
+                products.push((lo, a_lo / carrier));
+                products.push((hi, a_hi / carrier));
+                sum += (a_lo + a_hi) * (a_lo + a_hi);
+            }
+            Some(ImdResult {
+                products,
+                orders: None,
+                percent: 100.0 * sum.sqrt() / carrier,
+            })
+        }
+        // Relative to the sum of the two tones
+        ImdStandard::Ccif => {
+            let reference = (amplitude(frame, f1)? + amplitude(frame, f2)?).max(f32::MIN_POSITIVE);
+            let d2 = amplitude(frame, f2 - f1)?;
+            let d3_lo = amplitude(frame, 2.0 * f1 - f2)?;
+            let d3_hi = amplitude(frame, 2.0 * f2 - f1)?;
+            let second = 100.0 * d2 / reference;
+            let third = 100.0 * (d3_lo + d3_hi) / reference;
+            Some(ImdResult {
+                products: vec![
+                    (f2 - f1, d2 / reference),
+                    (2.0 * f1 - f2, d3_lo / reference),
+                    (2.0 * f2 - f1, d3_hi / reference),
+                ],
+                orders: Some((second, third)),
+                percent: (second * second + third * third).sqrt(),
+            })
+        }
+    }
+}
+
+// Runs the measurement over the last quarter second or so of the input. That is
+// longer than the analyzer blocks, so the products next to the tones are resolved,
+// and windowed with Blackman-Harris so the tones do not leak into them.
+pub struct Imd {
+    standard: ImdStandard,
+    sample_rate: usize,
+    fft: Arc<dyn RealToComplex<f32>>,
+    window: Vec<f32>,
+    history: Vec<f32>,
+}
+
+impl Imd {
+    pub fn new(standard: ImdStandard, sample_rate: usize) -> Imd {
+        let len = (sample_rate / 4).next_power_of_two();
+        Imd {
+            standard,
+            sample_rate,
+            fft: RealFftPlanner::<f32>::new().plan_fft_forward(len),
+            window: blackman_harris(len),
+            history: Vec::with_capacity(len * 2),
+        }
+    }
+
+    pub fn push(&mut self, block: &[f32]) -> Option<ImdResult> {
+        let len = self.window.len();
+        self.history.extend_from_slice(block);
+        if self.history.len() > len {
+            let drop = self.history.len() - len;
+            self.history.drain(0..drop);
+        }
+        if self.history.len() < len {
+            return None;
+        }
+
+        let mut buf: Vec<f32> = self
+            .history
+            .iter()
+            .zip(self.window.iter())
+            .map(|(x, w)| x * w)
+            .collect();
+        let mut spec = self.fft.make_output_vec();
+        self.fft.process(&mut buf, &mut spec).unwrap();
+        let frame = Frame {
+            bins: spec.iter().map(|x| x.norm() / len as f32).collect(),
+            axis: FreqAxis::Linear {
+                start: 0.0,
+                step: self.sample_rate as f32 / len as f32,
+            },
+            scale: Scale::Amplitude,
+            period: len as f32 / self.sample_rate as f32,
+        };
+        measure(&frame, self.standard)
+    }
+}
+
+pub struct ImdView {
+    pub open: bool,
+    standard: ImdStandard,
+    result: Option<ImdResult>,
+    history: VecDeque<f32>,
+}
+
+impl Default for ImdView {
+    fn default() -> Self {
+        Self {
+            open: false,
+            standard: ImdStandard::Smpte,
+            result: None,
+            history: VecDeque::new(),
+        }
+    }
+}
+
+impl ImdView {
+    pub fn set_result(&mut self, result: ImdResult) {
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(result.percent);
+        self.result = Some(result);
+    }
+
+    // Returns the measurement to run, or None to stop it, whenever that changes.
+    pub fn controls_ui(&mut self, ui: &mut egui::Ui) -> Option<Option<ImdStandard>> {
+        let old = (self.open, self.standard);
+        ui.checkbox(&mut self.open, "show");
+        ui.horizontal(|ui| {
+            for s in [ImdStandard::Smpte, ImdStandard::Ccif].iter() {
+                ui.radio_value(&mut self.standard, *s, s.name());
+            }
+        });
+        let (f1, f2, ratio) = self.standard.tones();
+        ui.label(format!(
+            "expects {} Hz and {} Hz at {}:1, see the generator",
+            f1, f2, ratio
+        ));
+
+        if (self.open, self.standard) != old {
+            self.result = None;
+            self.history.clear();
+            Some(if self.open { Some(self.standard) } else { None })
+        } else {
+            None
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        let result = match &self.result {
+            Some(r) => r,
+            None => {
+                ui.label("waiting for the tones");
+                return;
+            }
+        };
+        ui.heading(format!("IMD {:.4} %", result.percent));
+        if let Some((second, third)) = result.orders {
+            ui.label(format!("d2 {:.4} %, d3 {:.4} %", second, third));
+        }
+        for (hz, rel) in result.products.iter() {
+            ui.label(format!(
+                "{:8.0} Hz  {:7.1} dB",
+                hz,
+                20.0 * rel.max(1e-10).log10()
+            ));
+        }
+
+        let values = self
+            .history
+            .iter()
+            .enumerate()
+            .map(|(i, p)| Value::new(i as f64, *p as f64))
+            .collect();
+        ui.add(
+            Plot::new("imd history")
+                .curve(
+                    Curve::from_values(values)
+                        .color(egui::Color32::from_rgb(200, 100, 100))
+                        .name("IMD %"),
+                )
+                .include_y(0.0)
+                .allow_drag(false)
+                .height(120.0),
+        );
+    }
+}
@@ -0,0 +1,505 @@
+use super::common::*;
+use super::hilbert::analytic_signal;
+use anyhow::Result;
+use egui::widgets::plot::{Curve, Plot, Value};
+use realfft::RealFftPlanner;
+use std::f64::consts::PI;
+use std::path::Path;
+use std::sync::Arc;
+
+// Extra recording time after the sweep ends. Covers the round trip latency through
+// Jack and the system under test as well as the decay of the room.
+const CAPTURE_TAIL_SECS: f32 = 3.0;
+// Length of the impulse response kept after deconvolution and how much of it sits
+// before the main peak.
+const IR_LENGTH_SECS: f32 = 2.0;
+const IR_PRE_DELAY_SECS: f32 = 0.005;
+// Plots never get more points than this, larger traces are decimated.
+const MAX_PLOT_POINTS: usize = 2000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SweepParams {
+    pub start_hz: f32,
+    pub end_hz: f32,
+    pub duration: f32,
+    pub fade: f32,
+    pub sample_rate: usize,
+    // Level the generator plays the sweep at, low enough by default to spare
+    // loudspeakers and keep the system under test out of clipping
+    pub level_db: f32,
+}
+
+impl Default for SweepParams {
+    fn default() -> Self {
+        SweepParams {
+            start_hz: 20.0,
+            end_hz: 20000.0,
+            duration: 5.0,
+            fade: 0.05,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            level_db: -20.0,
+        }
+    }
+}
+
+impl SweepParams {
+    pub fn len(&self) -> usize {
+        (self.duration * self.sample_rate as f32) as usize
+    }
+
+    // Peak amplitude of the sweep, a full scale sine is 0 dBFS.
+    pub fn gain(&self) -> f32 {
+        10f32.powf(self.level_db / 20.0)
+    }
+
+    pub fn capture_len(&self) -> usize {
+        self.len() + (CAPTURE_TAIL_SECS * self.sample_rate as f32) as usize
+    }
+
+    // ln(w2 / w1), the sweep rate constant used by both the sweep and its inverse.
+    fn rate(&self) -> f64 {
+        (self.end_hz as f64 / self.start_hz as f64).ln()
+    }
+}
+
+fn fade_gain(i: usize, n: usize, fade: usize) -> f64 {
+    let edge = i.min(n - 1 - i);
+    if edge >= fade {
+        return 1.0;
+    }
+    return 0.5 - 0.5 * (PI * edge as f64 / fade as f64).cos();
+}
+
+// Farina exponential sine sweep with raised cosine fades at both ends.
+pub fn exp_sweep(params: &SweepParams) -> Vec<f32> {
+    let n = params.len();
+    let sr = params.sample_rate as f64;
+    let w1 = 2.0 * PI * params.start_hz as f64;
+    let t = params.duration as f64;
+    let l = params.rate();
+    let fade = (params.fade as f64 * sr) as usize;
+
+    (0..n)
+        .map(|i| {
+            let time = i as f64 / sr;
+            let x = (w1 * t / l * ((time * l / t).exp() - 1.0)).sin();
+            (x * fade_gain(i, n, fade)) as f32
+        })
+        .collect()
+}
+
+// The inverse filter is the time reversed sweep with an envelope that rises 6dB/oct,
+// compensating the pink spectrum of the exponential sweep.
+pub fn inverse_filter(params: &SweepParams, sweep: &[f32]) -> Vec<f32> {
+    let sr = params.sample_rate as f64;
+    let t = params.duration as f64;
+    let l = params.rate();
+
+    sweep
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, x)| x * (-(i as f64 / sr) * l / t).exp() as f32)
+        .collect()
+}
+
+// Linear convolution through the frequency domain.
+pub fn convolve(a: &[f32], b: &[f32]) -> Vec<f32> {
+    let out_len = a.len() + b.len() - 1;
+    let n = out_len.next_power_of_two();
+    let mut planner = RealFftPlanner::<f32>::new();
+    let fwd = planner.plan_fft_forward(n);
+    let inv = planner.plan_fft_inverse(n);
+
+    let mut a_buf = fwd.make_input_vec();
+    let mut b_buf = fwd.make_input_vec();
+    a_buf[..a.len()].copy_from_slice(a);
+    b_buf[..b.len()].copy_from_slice(b);
+
+    let mut a_spec = fwd.make_output_vec();
+    let mut b_spec = fwd.make_output_vec();
+    fwd.process(&mut a_buf, &mut a_spec).unwrap();
+    fwd.process(&mut b_buf, &mut b_spec).unwrap();
+
+    let scale = 1.0 / n as f32;
+    for (x, y) in a_spec.iter_mut().zip(b_spec.iter()) {
+        *x = *x * y * scale;
+    }
+
+    let mut out = inv.make_output_vec();
+    inv.process(&mut a_spec, &mut out).unwrap();
+    out.truncate(out_len);
+    out
+}
+
+fn abs_max(x: &[f32]) -> (usize, f32) {
+    x.iter().enumerate().fold((0, 0.0), |(idx, max), (i, v)| {
+        if v.abs() > max {
+            (i, v.abs())
+        } else {
+            (idx, max)
+        }
+    })
+}
+
+// Mean magnitude of x over a third of an octave around the geometric center of the sweep.
+fn mid_band_gain(params: &SweepParams, x: &[f32]) -> f32 {
+    let n = x.len().next_power_of_two();
+    let mut planner = RealFftPlanner::<f32>::new();
+    let fft = planner.plan_fft_forward(n);
+
+    let mut buf = fft.make_input_vec();
+    buf[..x.len()].copy_from_slice(x);
+    let mut spec = fft.make_output_vec();
+    fft.process(&mut buf, &mut spec).unwrap();
+
+    let bin_hz = params.sample_rate as f32 / n as f32;
+    let center = (params.start_hz * params.end_hz).sqrt();
+    let lo = (center * 2f32.powf(-1.0 / 6.0) / bin_hz) as usize;
+    let hi = (center * 2f32.powf(1.0 / 6.0) / bin_hz) as usize + 1;
+    let band = &spec[lo..hi];
+    band.iter().map(|v| v.norm()).sum::<f32>() / band.len() as f32
+}
+
+// Records the response of every input channel while the generator plays the sweep.
+pub struct Capture {
+    params: SweepParams,
+    recorded: Vec<Vec<f32>>,
+}
+
+impl Capture {
+    pub fn new(params: SweepParams, channels: usize) -> Capture {
+        Capture {
+            params,
+            recorded: vec![Vec::with_capacity(params.capture_len()); channels],
+        }
+    }
+
+    // Takes interleaved frames as they come out of the Jack ring buffer.
+    pub fn push(&mut self, interleaved: &[f32]) {
+        let channels = self.recorded.len();
+        for frame in interleaved.chunks(channels) {
+            if self.is_done() {
+                break;
+            }
+            for (rec, sample) in self.recorded.iter_mut().zip(frame.iter()) {
+                rec.push(*sample);
+            }
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.recorded[0].len() >= self.params.capture_len()
+    }
+
+    pub fn finish(self) -> ImpulseResponse {
+        ImpulseResponse::from_recording(&self.params, &self.recorded)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ImpulseResponse {
+    pub channels: Vec<Vec<f32>>,
+    pub sample_rate: usize,
+}
+
+impl ImpulseResponse {
+    pub fn from_recording(params: &SweepParams, recorded: &[Vec<f32>]) -> ImpulseResponse {
+        let sweep = exp_sweep(params);
+        let inverse = inverse_filter(params, &sweep);
+
+        // Scale so that the sweep deconvolved with itself has unity gain in the
+        // middle of the swept band, whatever level it was played at.
+        let norm = 1.0 / (mid_band_gain(params, &convolve(&sweep, &inverse)) * params.gain());
+
+        let full: Vec<Vec<f32>> = recorded
+            .iter()
+            .map(|rec| {
+                let mut ir = convolve(rec, &inverse);
+                ir.iter_mut().for_each(|x| *x *= norm);
+                ir
+            })
+            .collect();
+
+        // The linear response starts at the end of the sweep, the harmonic distortion
+        // products land before it. All channels are cut at the same point, relative
+        // to the loudest peak, so their timing can still be compared.
+        let linear_start = sweep.len() - 1;
+        let peak = full
+            .iter()
+            .map(|ir| abs_max(&ir[linear_start..]))
+            .fold((0, 0.0), |best, p| if p.1 > best.1 { p } else { best })
+            .0
+            + linear_start;
+
+        let sr = params.sample_rate as f32;
+        let start = peak.saturating_sub((IR_PRE_DELAY_SECS * sr) as usize);
+        let len = (IR_LENGTH_SECS * sr) as usize;
+
+        let channels = full
+            .iter()
+            .map(|ir| {
+                let end = (start + len).min(ir.len());
+                ir[start..end].to_vec()
+            })
+            .collect();
+
+        ImpulseResponse {
+            channels,
+            sample_rate: params.sample_rate,
+        }
+    }
+
+    // Energy time curve in dB relative to the peak.
+    pub fn etc(&self, channel: usize) -> Vec<f32> {
+        let energy: Vec<f32> = analytic_signal(&self.channels[channel])
+            .iter()
+            .map(|x| x.norm_sqr())
+            .collect();
+        let max = energy.iter().cloned().fold(f32::MIN_POSITIVE, f32::max);
+        energy
+            .iter()
+            .map(|e| 10.0 * (e.max(f32::MIN_POSITIVE) / max).log10())
+            .collect()
+    }
+
+    // Returns (frequencies in Hz, magnitude in dB, phase in degrees).
+    pub fn frequency_response(&self, channel: usize) -> (Vec<f32>, Vec<f32>, Vec<f32>) {
+        let ir = &self.channels[channel];
+        let n = ir.len().next_power_of_two();
+        let mut planner = RealFftPlanner::<f32>::new();
+        let fft = planner.plan_fft_forward(n);
+
+        let mut buf = fft.make_input_vec();
+        buf[..ir.len()].copy_from_slice(ir);
+        let mut spec = fft.make_output_vec();
+        fft.process(&mut buf, &mut spec).unwrap();
+
+        let bin_hz = self.sample_rate as f32 / n as f32;
+        let freqs = (0..spec.len()).map(|i| i as f32 * bin_hz).collect();
+        let mag = spec
+            .iter()
+            .map(|x| 20.0 * x.norm().max(f32::MIN_POSITIVE).log10())
+            .collect();
+        let phase = spec.iter().map(|x| x.arg().to_degrees()).collect();
+
+        (freqs, mag, phase)
+    }
+
+    pub fn write_wav(&self, path: &Path) -> Result<()> {
+        let spec = hound::WavSpec {
+            channels: self.channels.len() as u16,
+            sample_rate: self.sample_rate as u32,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut writer = hound::WavWriter::create(path, spec)?;
+        let len = self.channels.iter().map(|c| c.len()).min().unwrap_or(0);
+        for i in 0..len {
+            for channel in &self.channels {
+                writer.write_sample(channel[i])?;
+            }
+        }
+        writer.finalize()?;
+        Ok(())
+    }
+}
+
+// Keeps the loudest value of each chunk so that short peaks survive decimation.
+fn decimate_time(data: &[f32], sample_rate: usize) -> Vec<Value> {
+    let step = (data.len() / MAX_PLOT_POINTS).max(1);
+    let ms_per_sample = 1000.0 / sample_rate as f64;
+    data.chunks(step)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let peak = chunk
+                .iter()
+                .cloned()
+                .fold(0.0, |a: f32, b| if b.abs() > a.abs() { b } else { a });
+            Value::new((i * step) as f64 * ms_per_sample, peak as f64)
+        })
+        .collect()
+}
+
+// Samples a frequency trace at log spaced points, x is log10(Hz).
+fn decimate_log(freqs: &[f32], data: &[f32]) -> Vec<Value> {
+    let min = freqs[1].log10();
+    let max = freqs[freqs.len() - 1].log10();
+    let bin_hz = freqs[1];
+    let mut values: Vec<Value> = vec![];
+    let mut last = 0;
+    for i in 0..MAX_PLOT_POINTS {
+        let f = 10f32.powf(min + (max - min) * i as f32 / (MAX_PLOT_POINTS - 1) as f32);
+        let bin = ((f / bin_hz).round() as usize).min(data.len() - 1);
+        if bin != last {
+            values.push(Value::new(f.log10() as f64, data[bin] as f64));
+            last = bin;
+        }
+    }
+    values
+}
+
+pub struct ImpulseView {
+    pub open: bool,
+    params: SweepParams,
+    channel: usize,
+    export_path: String,
+    status: String,
+    // Held so a sweep buffer is never freed from inside the Jack process callback.
+    // Replaced sweeps are retired until the generator has let go of them as well.
+    sweep: Option<Arc<Vec<f32>>>,
+    retired: Vec<Arc<Vec<f32>>>,
+    response: Option<ImpulseResponse>,
+    waveform: Vec<Value>,
+    etc: Vec<Value>,
+    magnitude: Vec<Value>,
+    phase: Vec<Value>,
+}
+
+impl Default for ImpulseView {
+    fn default() -> Self {
+        Self {
+            open: false,
+            params: SweepParams::default(),
+            channel: 0,
+            export_path: "impulse.wav".to_owned(),
+            status: String::new(),
+            sweep: None,
+            retired: vec![],
+            response: None,
+            waveform: vec![],
+            etc: vec![],
+            magnitude: vec![],
+            phase: vec![],
+        }
+    }
+}
+
+impl ImpulseView {
+    pub fn set_response(&mut self, response: ImpulseResponse) {
+        self.status = "measurement done".to_owned();
+        self.response = Some(response);
+        self.channel = 0;
+        self.refresh();
+    }
+
+    fn refresh(&mut self) {
+        let response = match &self.response {
+            Some(r) => r,
+            None => return,
+        };
+        let sr = response.sample_rate;
+        let (freqs, mag, phase) = response.frequency_response(self.channel);
+
+        self.waveform = decimate_time(&response.channels[self.channel], sr);
+        self.etc = decimate_time(&response.etc(self.channel), sr);
+        self.magnitude = decimate_log(&freqs, &mag);
+        self.phase = decimate_log(&freqs, &phase);
+    }
+
+    // Returns the sweep to play and capture when the user starts a measurement.
+    pub fn controls_ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        sample_rate: usize,
+    ) -> Option<(SweepParams, Arc<Vec<f32>>)> {
+        // only this view still holds them once the generator is done
+        self.retired.retain(|s| Arc::strong_count(s) > 1);
+
+        let params = &mut self.params;
+        ui.checkbox(&mut self.open, "show");
+        ui.add(egui::Slider::new(&mut params.start_hz, 10.0..=1000.0).text("start Hz"));
+        ui.add(egui::Slider::new(&mut params.end_hz, 1000.0..=24000.0).text("end Hz"));
+        ui.add(egui::Slider::new(&mut params.duration, 1.0..=30.0).text("length s"));
+        ui.add(egui::Slider::new(&mut params.fade, 0.0..=0.5).text("fade s"));
+        ui.add(egui::Slider::new(&mut params.level_db, -60.0..=0.0).text("level dBFS"));
+
+        let mut started = None;
+        if ui.button("Measure").clicked() {
+            params.sample_rate = sample_rate;
+            params.end_hz = params.end_hz.min(sample_rate as f32 / 2.0);
+            let sweep = Arc::new(exp_sweep(params));
+            self.retired.extend(self.sweep.replace(sweep.clone()));
+            self.status = "measuring...".to_owned();
+            self.open = true;
+            started = Some((*params, sweep));
+        }
+
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.export_path);
+            if ui.button("Export WAV").clicked() {
+                self.status = match &self.response {
+                    Some(r) => match r.write_wav(Path::new(&self.export_path)) {
+                        Ok(()) => format!("wrote {}", self.export_path),
+                        Err(e) => format!("export failed: {}", e),
+                    },
+                    None => "nothing to export".to_owned(),
+                };
+            }
+        });
+        ui.label(&self.status);
+
+        started
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        let channels = self.response.as_ref().map_or(0, |r| r.channels.len());
+        if channels == 0 {
+            ui.label("no measurement yet");
+            return;
+        }
+
+        let mut channel = self.channel;
+        ui.horizontal(|ui| {
+            for c in 0..channels {
+                ui.radio_value(&mut channel, c, format!("in {}", c + 1));
+            }
+        });
+        if channel != self.channel {
+            self.channel = channel;
+            self.refresh();
+        }
+
+        let height = 140.0;
+        let color = egui::Color32::from_rgb(200, 100, 100);
+        ui.add(
+            Plot::new("ir waveform")
+                .curve(
+                    Curve::from_values(self.waveform.clone())
+                        .color(color)
+                        .name("ms"),
+                )
+                .height(height),
+        );
+        ui.add(
+            Plot::new("ir etc")
+                .curve(
+                    Curve::from_values(self.etc.clone())
+                        .color(color)
+                        .name("ETC dB"),
+                )
+                .include_y(0.0)
+                .height(height),
+        );
+        ui.add(
+            Plot::new("ir magnitude")
+                .curve(
+                    Curve::from_values(self.magnitude.clone())
+                        .color(color)
+                        .name("dB / log10 Hz"),
+                )
+                .height(height),
+        );
+        ui.add(
+            Plot::new("ir phase")
+                .curve(
+                    Curve::from_values(self.phase.clone())
+                        .color(color)
+                        .name("deg / log10 Hz"),
+                )
+                .include_y(-180.0)
+                .include_y(180.0)
+                .height(height),
+        );
+    }
+}
@@ -0,0 +1,393 @@
+use super::common::*;
+use super::psd::hann;
+use super::zoom::low_pass;
+use num_complex::Complex64;
+use std::f64::consts::PI;
+
+// Points of the envelope between 0 and the formant ceiling
+const ENVELOPE_POINTS: usize = 512;
+const MAX_FORMANTS: usize = 4;
+// Roots outside these limits are not formants
+const MIN_FORMANT_HZ: f32 = 90.0;
+const MAX_BANDWIDTH_HZ: f32 = 500.0;
+// Pre-emphasis boosts everything above this by 6 dB per octave
+const PRE_EMPHASIS_HZ: f64 = 50.0;
+
+const FORMANT_COLORS: [(u8, u8, u8); MAX_FORMANTS] = [
+    (240, 60, 60),
+    (240, 160, 40),
+    (230, 230, 60),
+    (80, 200, 240),
+];
+
+pub fn formant_color(formant: usize) -> egui::Color32 {
+    let (r, g, b) = FORMANT_COLORS[formant % MAX_FORMANTS];
+    egui::Color32::from_rgb(r, g, b)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LpcMethod {
+    Autocorrelation,
+    Burg,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LpcParams {
+    pub method: LpcMethod,
+    pub order: usize,
+    // Length of the analyzed stretch at the end of each block
+    pub window_ms: f32,
+    // The signal is decimated to about twice this before the analysis
+    pub ceiling_hz: f32,
+}
+
+impl LpcParams {
+    fn decimation(&self, sample_rate: usize) -> usize {
+        ((sample_rate as f32 / (2.0 * self.ceiling_hz)) as usize).max(1)
+    }
+
+    // Longest window whose decimated samples all come out of a block of analysis
+    // input, longer ones would be made up of the same samples over and over.
+    pub fn max_window_ms(&self, sample_rate: usize, block: usize) -> f32 {
+        let decimation = self.decimation(sample_rate);
+        let taps = kernel_len(decimation);
+        // one extra sample feeds the pre-emphasis of the first one
+        let len = (block.saturating_sub(taps) / decimation).saturating_sub(1);
+        1000.0 * (len * decimation) as f32 / sample_rate as f32
+    }
+}
+
+fn kernel_len(decimation: usize) -> usize {
+    16 * decimation + 1
+}
+
+impl Default for LpcParams {
+    fn default() -> Self {
+        LpcParams {
+            method: LpcMethod::Burg,
+            order: 10,
+            window_ms: 30.0,
+            ceiling_hz: 5500.0,
+        }
+    }
+}
+
+// Prediction coefficients a, with a[0] = 1 and the predictor error
+// e[n] = x[n] + a[1] x[n - 1] + ... + a[order] x[n - order], and the error power.
+// Autocorrelation method with the Levinson-Durbin recursion.
+pub fn autocorrelation_lpc(x: &[f32], order: usize) -> (Vec<f64>, f64) {
+    let r: Vec<f64> = (0..=order)
+        .map(|k| {
+            x.iter()
+                .zip(x[k.min(x.len())..].iter())
+                .map(|(a, b)| *a as f64 * *b as f64)
+                .sum()
+        })
+        .collect();
+    let mut a = vec![0.0; order + 1];
+    a[0] = 1.0;
+    let mut error = r[0];
+    for i in 1..=order {
+        if error <= 0.0 {
+            break;
+        }
+        let acc: f64 = (1..i).map(|j| a[j] * r[i - j]).sum();
+        let k = -(r[i] + acc) / error;
+        let prev = a.clone();
+        for j in 1..i {
+            a[j] = prev[j] + k * prev[i - j];
+        }
+        a[i] = k;
+        error *= 1.0 - k * k;
+    }
+    (a, error.max(0.0))
+}
+
+// Same as autocorrelation_lpc but with Burg's method, which fits the forward and
+// backward prediction errors of the data itself and does better on short windows.
+pub fn burg_lpc(x: &[f32], order: usize) -> (Vec<f64>, f64) {
+    let n = x.len();
+    let mut f: Vec<f64> = x.iter().map(|v| *v as f64).collect();
+    let mut b = f.clone();
+    let mut a = vec![0.0; order + 1];
+    a[0] = 1.0;
+    let mut error = f.iter().map(|v| v * v).sum::<f64>() / n as f64;
+    for m in 1..=order.min(n - 1) {
+        let mut num = 0.0;
+        let mut den = 0.0;
+        for i in m..n {
+            num += f[i] * b[i - 1];
+            den += f[i] * f[i] + b[i - 1] * b[i - 1];
+        }
+        if den == 0.0 {
+            break;
+        }
+        let k = -2.0 * num / den;
+
+        let prev = a.clone();
+        for j in 1..m {
+            a[j] = prev[j] + k * prev[m - j];
+        }
+        a[m] = k;
+
+        // going down keeps b[i - 1] at its old value until it has been used
+        for i in (m..n).rev() {
+            let fi = f[i];
+            f[i] = fi + k * b[i - 1];
+            b[i] = b[i - 1] + k * fi;
+        }
+        error *= 1.0 - k * k;
+    }
+    (a, error)
+}
+
+// Roots of the monic polynomial z^n + c[1] z^(n-1) + ... + c[n] by Durand-Kerner.
+fn roots(c: &[f64]) -> Vec<Complex64> {
+    let n = c.len() - 1;
+    let eval = |z: Complex64| {
+        c.iter()
+            .fold(Complex64::new(0.0, 0.0), |acc, c| acc * z + c)
+    };
+    let seed = Complex64::new(0.4, 0.9);
+    let mut z: Vec<Complex64> = (0..n).map(|i| seed.powu(i as u32)).collect();
+    for _ in 0..500 {
+        let mut moved = 0.0;
+        for i in 0..n {
+            let denom = (0..n)
+                .filter(|j| *j != i)
+                .fold(Complex64::new(1.0, 0.0), |acc, j| acc * (z[i] - z[j]));
+            if denom.norm() == 0.0 {
+                continue;
+            }
+            let step = eval(z[i]) / denom;
+            z[i] -= step;
+            moved += step.norm();
+        }
+        if moved < 1e-12 {
+            break;
+        }
+    }
+    z
+}
+
+// Frequencies and bandwidths in Hz of the formants of a predictor, lowest first.
+pub fn formants(a: &[f64], sample_rate: f32) -> Vec<(f32, f32)> {
+    let mut found: Vec<(f32, f32)> = roots(a)
+        .iter()
+        .filter(|z| z.im > 0.0)
+        .map(|z| {
+            let hz = z.arg() as f32 * sample_rate / (2.0 * PI as f32);
+            let bandwidth = -(z.norm().ln() as f32) * sample_rate / PI as f32;
+            (hz, bandwidth)
+        })
+        .filter(|(hz, bw)| {
+            *hz > MIN_FORMANT_HZ && *hz < sample_rate / 2.0 - 50.0 && *bw < MAX_BANDWIDTH_HZ
+        })
+        .collect();
+    found.sort_by(|x, y| x.0.partial_cmp(&y.0).unwrap());
+    found.truncate(MAX_FORMANTS);
+    found
+}
+
+#[derive(Debug, Clone)]
+pub struct LpcFrame {
+    // Smooth spectral envelope, with the pre-emphasis taken back out so it follows
+    // the spectrum. Only its shape is meaningful, the level is arbitrary.
+    pub envelope: Frame,
+    // Frequency and bandwidth in Hz of F1 upwards
+    pub formants: Vec<(f32, f32)>,
+}
+
+// Linear prediction of the end of each analysis block. The block is low pass
+// filtered and decimated so the predictor only models the band the formants live
+// in, then pre-emphasized and windowed.
+pub struct Lpc {
+    params: LpcParams,
+    sample_rate: usize,
+    decimation: usize,
+    kernel: Vec<f32>,
+    window: Vec<f32>,
+    pre_emphasis: f64,
+}
+
+impl Lpc {
+    // The analysis gets the last block samples at a time.
+    pub fn new(params: LpcParams, sample_rate: usize, block: usize) -> Lpc {
+        let params = LpcParams {
+            window_ms: params
+                .window_ms
+                .min(params.max_window_ms(sample_rate, block)),
+            ..params
+        };
+        let decimation = params.decimation(sample_rate);
+        let rate = sample_rate as f64 / decimation as f64;
+        let len = ((params.window_ms / 1000.0) as f64 * rate) as usize;
+        Lpc {
+            params,
+            sample_rate,
+            decimation,
+            kernel: low_pass(kernel_len(decimation), 0.5 / decimation as f64),
+            window: hann(len.max(params.order + 2)),
+            pre_emphasis: (-2.0 * PI * PRE_EMPHASIS_HZ / rate).exp(),
+        }
+    }
+
+    fn rate(&self) -> f64 {
+        self.sample_rate as f64 / self.decimation as f64
+    }
+
+    pub fn process(&self, signal: &[f32]) -> LpcFrame {
+        let len = self.window.len();
+        let taps = self.kernel.len();
+        // one extra sample feeds the pre-emphasis of the first one
+        let needed = (len + 1) * self.decimation + taps;
+        let start = signal.len().saturating_sub(needed);
+        let decimated: Vec<f32> = (0..=len)
+            .map(|m| {
+                let from = (start + m * self.decimation).min(signal.len().saturating_sub(taps));
+                signal[from..from + taps]
+                    .iter()
+                    .zip(self.kernel.iter())
+                    .map(|(x, k)| x * k)
+                    .sum()
+            })
+            .collect();
+        let mu = self.pre_emphasis as f32;
+        let x: Vec<f32> = (0..len)
+            .map(|i| (decimated[i + 1] - mu * decimated[i]) * self.window[i])
+            .collect();
+
+        let (a, error) = match self.params.method {
+            LpcMethod::Autocorrelation => autocorrelation_lpc(&x, self.params.order),
+            LpcMethod::Burg => burg_lpc(&x, self.params.order),
+        };
+
+        let rate = self.rate();
+        let max_hz = rate / 2.0;
+        let step = max_hz / (ENVELOPE_POINTS - 1) as f64;
+        let gain = error.sqrt();
+        let bins = (0..ENVELOPE_POINTS)
+            .map(|i| {
+                let w = 2.0 * PI * i as f64 * step / rate;
+                let z = Complex64::from_polar(1.0, -w);
+                let response = a
+                    .iter()
+                    .rev()
+                    .fold(Complex64::new(0.0, 0.0), |acc, c| acc * z + c);
+                let emphasis = (Complex64::new(1.0, 0.0) - z * self.pre_emphasis).norm();
+                (gain / (response.norm() * emphasis).max(1e-12)) as f32
+            })
+            .collect();
+
+        LpcFrame {
+            envelope: Frame {
+                bins,
+                axis: FreqAxis::Linear {
+                    start: 0.0,
+                    step: step as f32,
+                },
+                scale: Scale::Amplitude,
+                period: signal.len() as f32 / self.sample_rate as f32,
+            },
+            formants: formants(&a, rate as f32),
+        }
+    }
+}
+
+pub struct LpcView {
+    pub enabled: bool,
+    params: LpcParams,
+    latest: Option<LpcFrame>,
+}
+
+impl Default for LpcView {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            params: LpcParams::default(),
+            latest: None,
+        }
+    }
+}
+
+impl LpcView {
+    pub fn set_frame(&mut self, frame: LpcFrame) {
+        self.latest = Some(frame);
+    }
+
+    // Envelope to overlay on the spectrum.
+    pub fn envelope(&self) -> Option<&Frame> {
+        match (self.enabled, &self.latest) {
+            (true, Some(f)) => Some(&f.envelope),
+            _ => None,
+        }
+    }
+
+    // Spectrograph marks of the latest formants, for a row spanning min_hz to max_hz.
+    pub fn marks(&self, min_hz: f32, max_hz: f32, log: bool) -> Vec<(f32, egui::Color32)> {
+        match (self.enabled, &self.latest) {
+            (true, Some(f)) => f
+                .formants
+                .iter()
+                .enumerate()
+                .map(|(i, (hz, _))| {
+                    (
+                        super::spectrograph::position(*hz, min_hz, max_hz, log),
+                        formant_color(i),
+                    )
+                })
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    // Returns the analysis to run, or None to stop it, whenever that changes.
+    pub fn controls_ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        sample_rate: usize,
+    ) -> Option<Option<LpcParams>> {
+        let old = (self.enabled, self.params);
+        let params = &mut self.params;
+        ui.checkbox(&mut self.enabled, "envelope and formants");
+        ui.horizontal(|ui| {
+            ui.radio_value(
+                &mut params.method,
+                LpcMethod::Autocorrelation,
+                "autocorrelation",
+            );
+            ui.radio_value(&mut params.method, LpcMethod::Burg, "Burg");
+        });
+        ui.add(egui::Slider::new(&mut params.order, 2..=40).text("order"));
+        ui.add(egui::Slider::new(&mut params.ceiling_hz, 1000.0..=10000.0).text("ceiling Hz"));
+        // the analyzer hands over FFT_MAX_SIZE samples at a time
+        let max_window_ms = params
+            .max_window_ms(sample_rate, FFT_MAX_SIZE)
+            .max(5.0)
+            .min(150.0);
+        params.window_ms = params.window_ms.min(max_window_ms);
+        ui.add(egui::Slider::new(&mut params.window_ms, 5.0..=max_window_ms).text("window ms"));
+
+        if let (true, Some(frame)) = (self.enabled, &self.latest) {
+            for (i, (hz, bw)) in frame.formants.iter().enumerate() {
+                ui.colored_label(
+                    formant_color(i),
+                    format!("F{} {:.0} Hz, bandwidth {:.0} Hz", i + 1, hz, bw),
+                );
+            }
+        }
+
+        if (self.enabled, self.params) != old {
+            if !self.enabled {
+                self.latest = None;
+            }
+            Some(if self.enabled {
+                Some(self.params)
+            } else {
+                None
+            })
+        } else {
+            None
+        }
+    }
+}
@@ -1,5 +1,23 @@
 pub mod app;
+pub mod cepstrum;
 pub mod common;
 pub mod controllers;
+pub mod cwt;
+pub mod generator;
+pub mod goertzel;
+pub mod goniometer;
+pub mod hilbert;
+pub mod hpss;
+pub mod imd;
+pub mod impulse;
+pub mod lpc;
+pub mod multires;
+pub mod octave;
+pub mod patchbay;
+pub mod phase;
+pub mod player;
+pub mod psd;
+pub mod scope;
 pub mod spectrograph;
 pub mod spectrum;
+pub mod zoom;
@@ -0,0 +1,169 @@
+use super::common::*;
+use super::psd::hann;
+use num_complex::Complex32;
+use realfft::{RealFftPlanner, RealToComplex};
+use std::sync::Arc;
+
+pub const TIERS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MultiResParams {
+    // FFT size of each tier, the longest covers the lowest band
+    pub sizes: [usize; TIERS],
+    // Frequencies where one tier hands over to the next
+    pub crossovers: [f32; TIERS - 1],
+}
+
+impl Default for MultiResParams {
+    fn default() -> Self {
+        MultiResParams {
+            sizes: [16384, 4096, 1024],
+            crossovers: [250.0, 2500.0],
+        }
+    }
+}
+
+struct Tier {
+    size: usize,
+    fft: Arc<dyn RealToComplex<f32>>,
+    window: Vec<f32>,
+    window_sum: f32,
+    buf: Vec<f32>,
+    spec: Vec<Complex32>,
+    // magnitude of the latest transform
+    bins: Vec<f32>,
+    // samples since the latest transform
+    since: usize,
+}
+
+// Spectra of several FFT sizes stitched into one frame. Each tier is transformed
+// every half of its own length and the frame is reported at the rate of the
+// shortest tier, so high bands get a fine time resolution and low bands a fine
+// frequency resolution.
+pub struct MultiRes {
+    sample_rate: usize,
+    tiers: Vec<Tier>,
+    history: Vec<f32>,
+    longest: usize,
+    hop: usize,
+    since: usize,
+    // tier and fractional tier bin of every output bin
+    map: Vec<(usize, f32)>,
+}
+
+impl MultiRes {
+    pub fn new(params: MultiResParams, sample_rate: usize) -> MultiRes {
+        let mut planner = RealFftPlanner::<f32>::new();
+        let mut sizes = params.sizes;
+        sizes.sort_unstable_by(|a, b| b.cmp(a));
+        let mut crossovers = params.crossovers;
+        crossovers.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        let tiers: Vec<Tier> = sizes
+            .iter()
+            .map(|size| {
+                let fft = planner.plan_fft_forward(*size);
+                let window = hann(*size);
+                Tier {
+                    size: *size,
+                    window_sum: window.iter().sum(),
+                    window,
+                    buf: fft.make_input_vec(),
+                    spec: fft.make_output_vec(),
+                    bins: vec![0.0; size / 2 + 1],
+                    since: *size,
+                    fft,
+                }
+            })
+            .collect();
+
+        // the output uses the bin spacing of the longest tier
+        let longest = sizes[0];
+        let step = sample_rate as f32 / longest as f32;
+        let map = (0..longest / 2 + 1)
+            .map(|i| {
+                let hz = i as f32 * step;
+                let t = crossovers.iter().position(|c| hz < *c).unwrap_or(TIERS - 1);
+                (t, hz * sizes[t] as f32 / sample_rate as f32)
+            })
+            .collect();
+
+        MultiRes {
+            sample_rate,
+            hop: sizes[TIERS - 1] / 2,
+            since: 0,
+            history: Vec::with_capacity(longest * 2),
+            longest,
+            tiers,
+            map,
+        }
+    }
+
+    pub fn push(&mut self, samples: &[f32]) -> Vec<Frame> {
+        let mut frames = vec![];
+        let mut rest = samples;
+        while !rest.is_empty() {
+            let take = (self.hop - self.since).min(rest.len());
+            self.history.extend_from_slice(&rest[..take]);
+            self.since += take;
+            rest = &rest[take..];
+            if self.since < self.hop {
+                break;
+            }
+            self.since = 0;
+
+            if self.history.len() > self.longest {
+                let excess = self.history.len() - self.longest;
+                self.history.drain(0..excess);
+            }
+            for tier in self.tiers.iter_mut() {
+                tier.since += self.hop;
+            }
+            if self.history.len() == self.longest {
+                frames.push(self.frame());
+            }
+        }
+        frames
+    }
+
+    fn frame(&mut self) -> Frame {
+        let len = self.history.len();
+        for tier in self.tiers.iter_mut() {
+            if tier.since < tier.size / 2 {
+                continue;
+            }
+            tier.since = 0;
+            let latest = &self.history[len - tier.size..];
+            for i in 0..tier.size {
+                tier.buf[i] = latest[i] * tier.window[i];
+            }
+            tier.fft.process(&mut tier.buf, &mut tier.spec).unwrap();
+            // a sine of amplitude A reads A / 2 whatever the size, like the plain spectrum
+            for (b, x) in tier.bins.iter_mut().zip(tier.spec.iter()) {
+                *b = x.norm() / tier.window_sum;
+            }
+        }
+
+        let bins = self
+            .map
+            .iter()
+            .map(|(t, pos)| {
+                let bins = &self.tiers[*t].bins;
+                let lo = (pos.floor() as usize).min(bins.len() - 1);
+                let hi = (lo + 1).min(bins.len() - 1);
+                let frac = pos - lo as f32;
+                bins[lo] * (1.0 - frac) + bins[hi] * frac
+            })
+            .collect();
+
+        Frame {
+            bins,
+            axis: FreqAxis::Linear {
+                start: 0.0,
+                step: self.sample_rate as f32 / self.longest as f32,
+            },
+            scale: Scale::Amplitude,
+            period: self.hop as f32 / self.sample_rate as f32,
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use super::common::*;
+
+const FRACTIONS: [usize; 5] = [1, 3, 6, 12, 24];
+const REFERENCE_HZ: f64 = 1000.0;
+const MIN_HZ: f32 = 20.0;
+const MAX_HZ: f32 = 20000.0;
+// ISO 266 R10 preferred numbers, nominal labels of octave and third octave bands
+const PREFERRED: [f32; 10] = [1.0, 1.25, 1.6, 2.0, 2.5, 3.15, 4.0, 5.0, 6.3, 8.0];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Band {
+    // band number relative to the 1 kHz reference band
+    pub x: i32,
+    pub center: f32,
+    pub lo: f32,
+    pub hi: f32,
+}
+
+// IEC 61260-1 base ten octave ratio
+fn octave_ratio() -> f64 {
+    10f64.powf(0.3)
+}
+
+// The 1/fraction octave bands that overlap min_hz to max_hz.
+pub fn bands(fraction: usize, min_hz: f32, max_hz: f32) -> Vec<Band> {
+    let g = octave_ratio();
+    let b = fraction as f64;
+    let center = |x: i32| -> f64 {
+        if fraction % 2 == 1 {
+            REFERENCE_HZ * g.powf(x as f64 / b)
+        } else {
+            REFERENCE_HZ * g.powf((2.0 * x as f64 + 1.0) / (2.0 * b))
+        }
+    };
+
+    let band = |x: i32| -> Band {
+        let fm = center(x);
+        Band {
+            x,
+            center: fm as f32,
+            lo: (fm * g.powf(-1.0 / (2.0 * b))) as f32,
+            hi: (fm * g.powf(1.0 / (2.0 * b))) as f32,
+        }
+    };
+
+    // a band is kept as long as it reaches into the range
+    let mut x = 0;
+    while band(x - 1).hi >= min_hz {
+        x -= 1;
+    }
+
+    let mut out = vec![];
+    while band(x).lo <= max_hz {
+        out.push(band(x));
+        x += 1;
+    }
+    out
+}
+
+// Power in each band. Bins straddling a band edge contribute the fraction of their
+// width that lies inside the band, which keeps narrow low bands honest.
+pub fn band_powers(frame: &Frame, bands: &[Band]) -> Vec<f32> {
+    let last = frame.bins.len() - 1;
+    bands
+        .iter()
+        .map(|band| {
+            let lo = frame.axis.bin(band.lo).floor().max(0.0) as usize;
+            let hi = (frame.axis.bin(band.hi).ceil().max(0.0) as usize).min(last);
+            (lo..=hi)
+                .map(|k| {
+                    let (bin_lo, bin_hi) = frame.axis.edges(k);
+                    let overlap = (band.hi.min(bin_hi) - band.lo.max(bin_lo)).max(0.0);
+                    frame.bin_power(k) * overlap / (bin_hi - bin_lo)
+                })
+                .sum()
+        })
+        .collect()
+}
+
+pub fn nominal_label(hz: f32, fraction: usize) -> String {
+    let mut nominal = hz;
+    if fraction <= 3 {
+        let decade = 10f32.powf(hz.log10().floor());
+        nominal = PREFERRED
+            .iter()
+            .chain([10.0].iter())
+            .map(|p| p * decade)
+            .min_by(|a, b| {
+                let da = (a / hz).ln().abs();
+                let db = (b / hz).ln().abs();
+                da.partial_cmp(&db).unwrap()
+            })
+            .unwrap();
+    }
+    let trimmed = |v: f32| -> String {
+        let s = format!("{:.2}", v);
+        s.trim_end_matches('0').trim_end_matches('.').to_owned()
+    };
+    if nominal >= 1000.0 {
+        format!("{}k", trimmed(nominal / 1000.0))
+    } else {
+        trimmed(nominal)
+    }
+}
+
+// Labels for the band nearest each octave centre, the bands in between and bands
+// at the ends of the range that do not hold an octave centre get none. With an even
+// fraction an octave centre falls on a band edge, the upper of the two bands gets
+// its label.
+pub fn octave_labels(bands: &[Band]) -> Vec<Option<String>> {
+    let g = octave_ratio();
+    // (octave, distance from its centre in octaves) of every band
+    let nearest: Vec<(i32, f64)> = bands
+        .iter()
+        .map(|b| {
+            let octaves = (b.center as f64 / REFERENCE_HZ).ln() / g.ln();
+            let k = octaves.round();
+            (k as i32, (octaves - k).abs())
+        })
+        .collect();
+    // bands half a band from the centre tie, up to rounding
+    let closer = |a: f64, b: f64| a < b - 1e-6;
+    (0..bands.len())
+        .map(|i| {
+            let (k, d) = nearest[i];
+            let below = i > 0 && nearest[i - 1].0 == k && closer(nearest[i - 1].1, d);
+            let above =
+                i + 1 < bands.len() && nearest[i + 1].0 == k && !closer(d, nearest[i + 1].1);
+            let hz = (REFERENCE_HZ * g.powi(k)) as f32;
+            let inside = hz >= bands[i].lo * 0.9999 && hz <= bands[i].hi * 1.0001;
+            if below || above || !inside {
+                None
+            } else {
+                Some(nominal_label(hz, 1))
+            }
+        })
+        .collect()
+}
+
+pub struct RtaView {
+    pub enabled: bool,
+    fraction: usize,
+    min_db: f32,
+    max_db: f32,
+    bands: Vec<Band>,
+    labels: Vec<Option<String>>,
+    // (fraction, upper frequency) the bands were computed for
+    bands_for: (usize, u32),
+}
+
+impl Default for RtaView {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            fraction: 3,
+            min_db: -120.0,
+            max_db: 0.0,
+            bands: vec![],
+            labels: vec![],
+            bands_for: (0, 0),
+        }
+    }
+}
+
+impl RtaView {
+    pub fn options_ui(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.enabled, "RTA bars");
+        if !self.enabled {
+            return;
+        }
+        let fraction = &mut self.fraction;
+        egui::ComboBox::from_label("bands")
+            .selected_text(format!("1/{} octave", fraction))
+            .show_ui(ui, |ui| {
+                for f in FRACTIONS.iter() {
+                    ui.selectable_value(fraction, *f, format!("1/{} octave", f));
+                }
+            });
+        ui.add(egui::Slider::new(&mut self.min_db, -200.0..=-20.0).text("floor dB"));
+        ui.add(egui::Slider::new(&mut self.max_db, -60.0..=40.0).text("top dB"));
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, height: f32, frame: Option<&Frame>, max_hz: f32) {
+        let size = egui::vec2(ui.available_width(), height);
+        let (response, painter) = ui.allocate_painter(size, egui::Sense::hover());
+        let frame = match frame {
+            Some(f) => f,
+            None => return,
+        };
+
+        let key = (self.fraction, max_hz as u32);
+        if self.bands_for != key {
+            self.bands = bands(self.fraction, MIN_HZ, MAX_HZ.min(max_hz));
+            self.labels = octave_labels(&self.bands);
+            self.bands_for = key;
+        }
+        if self.bands.is_empty() {
+            return;
+        }
+
+        let rect = response.rect;
+        let label_height = 14.0;
+        let bar_area = rect.height() - label_height;
+        let width = rect.width() / self.bands.len() as f32;
+        let bar_color = egui::Color32::from_rgb(200, 100, 100);
+        let text_color = egui::Color32::from_gray(180);
+        let range = self.max_db - self.min_db;
+
+        let powers = band_powers(frame, &self.bands);
+        for (i, (power, label)) in powers.iter().zip(self.labels.iter()).enumerate() {
+            let level = 10.0 * power.max(1e-30).log10();
+            let t = ((level - self.min_db) / range).clamp(0.0, 1.0);
+            let left = rect.left() + i as f32 * width;
+            let bottom = rect.top() + bar_area;
+            let bar = egui::Rect::from_min_max(
+                egui::pos2(left + 1.0, bottom - t * bar_area),
+                egui::pos2(left + width - 1.0, bottom),
+            );
+            painter.rect_filled(bar, 0.0, bar_color);
+
+            if let Some(label) = label {
+                painter.text(
+                    egui::pos2(left + width / 2.0, bottom),
+                    egui::Align2::CENTER_TOP,
+                    label,
+                    egui::TextStyle::Small,
+                    text_color,
+                );
+            }
+        }
+    }
+}
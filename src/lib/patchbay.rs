@@ -0,0 +1,220 @@
+use super::common::*;
+use anyhow::Result;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    // connect matching output ports to the analyzer inputs
+    In,
+    // connect the generator and player outputs to matching input ports
+    Out,
+}
+
+// Connects matching ports in order: the first match goes to port 1 and so on. In
+// rules share the analyzer inputs, a rule starts at the input after the last one
+// the rules before it took. The pattern is a POSIX extended regex over the whole
+// port name, as Jack matches them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectRule {
+    pub direction: Direction,
+    pub pattern: String,
+}
+
+impl ConnectRule {
+    // The pattern anchored, Jack otherwise matches it anywhere in the name.
+    pub fn anchored(&self) -> String {
+        format!("^({})$", self.pattern)
+    }
+}
+
+// Reads a rule from a config line, `in <regex>` or `out <regex>`.
+pub fn parse_rule(line: &str) -> Result<ConnectRule> {
+    let mut parts = line.trim().splitn(2, char::is_whitespace);
+    let direction = match parts.next() {
+        Some("in") => Direction::In,
+        Some("out") => Direction::Out,
+        _ => bail!("rule must start with in or out: {}", line),
+    };
+    let pattern = match parts.next().map(str::trim) {
+        Some(p) if !p.is_empty() => p.to_owned(),
+        _ => bail!("rule has no port pattern: {}", line),
+    };
+    Ok(ConnectRule { direction, pattern })
+}
+
+// Rules of a config file, one per line. Blank lines and lines starting with # are
+// skipped.
+pub fn parse_rules(text: &str) -> Result<Vec<ConnectRule>> {
+    text.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(parse_rule)
+        .collect()
+}
+
+// Audio ports as the patchbay last saw them.
+#[derive(Debug, Clone, Default)]
+pub struct PortGraph {
+    // ports of our own clients, in port order
+    pub inputs: Vec<String>,
+    pub outputs: Vec<String>,
+    // ports of other clients our inputs can be fed from, and our outputs can feed
+    pub sources: Vec<String>,
+    pub destinations: Vec<String>,
+    // (output port, input port) of every connection to one of our ports
+    pub connections: Vec<(String, String)>,
+    // connections that failed since the last graph
+    pub errors: Vec<String>,
+}
+
+impl PortGraph {
+    fn connected(&self, port: &str) -> Vec<&(String, String)> {
+        self.connections
+            .iter()
+            .filter(|(from, to)| from == port || to == port)
+            .collect()
+    }
+}
+
+pub struct PatchbayView {
+    pub open: bool,
+    graph: Option<PortGraph>,
+}
+
+impl Default for PatchbayView {
+    fn default() -> Self {
+        Self {
+            open: false,
+            graph: None,
+        }
+    }
+}
+
+impl PatchbayView {
+    pub fn set_graph(&mut self, graph: PortGraph) {
+        self.graph = Some(graph);
+    }
+
+    // One row per port of ours, with its connections and a picker to add one.
+    fn port_ui(
+        ui: &mut egui::Ui,
+        graph: &PortGraph,
+        port: &str,
+        input: bool,
+        commands: &mut Vec<PatchCommand>,
+    ) {
+        let short = port.rsplit(':').next().unwrap_or(port);
+        let candidates = if input {
+            &graph.sources
+        } else {
+            &graph.destinations
+        };
+        let mut picked: Option<String> = None;
+        ui.horizontal(|ui| {
+            ui.label(short);
+            egui::ComboBox::from_id_source(("patch", port))
+                .selected_text("connect...")
+                .show_ui(ui, |ui| {
+                    for other in candidates.iter() {
+                        if ui.selectable_label(false, other).clicked() {
+                            picked = Some(other.clone());
+                        }
+                    }
+                });
+        });
+        if let Some(other) = picked {
+            commands.push(match input {
+                true => PatchCommand::Connect(other, port.to_owned()),
+                false => PatchCommand::Connect(port.to_owned(), other),
+            });
+        }
+        for (from, to) in graph.connected(port) {
+            ui.horizontal(|ui| {
+                let other = if input { from } else { to };
+                ui.add_space(16.0);
+                if ui.small_button("x").clicked() {
+                    commands.push(PatchCommand::Disconnect(from.clone(), to.clone()));
+                }
+                ui.label(other);
+            });
+        }
+    }
+
+    pub fn controls_ui(&mut self, ui: &mut egui::Ui) -> Option<PatchCommand> {
+        ui.checkbox(&mut self.open, "show");
+        match &self.graph {
+            Some(g) => ui.label(format!(
+                "{} sources, {} destinations, {} connections",
+                g.sources.len(),
+                g.destinations.len(),
+                g.connections.len()
+            )),
+            None => ui.label("no ports from Jack yet"),
+        };
+        if let Some(g) = &self.graph {
+            for e in g.errors.iter() {
+                ui.colored_label(egui::Color32::from_rgb(220, 120, 100), e);
+            }
+        }
+        if ui.button("Refresh").clicked() {
+            Some(PatchCommand::Refresh)
+        } else {
+            None
+        }
+    }
+
+    // Returns the connections to make or break.
+    pub fn ui(&mut self, ui: &mut egui::Ui) -> Vec<PatchCommand> {
+        let mut commands = vec![];
+        let graph = match &self.graph {
+            Some(g) => g,
+            None => return commands,
+        };
+        ui.label("inputs");
+        for port in graph.inputs.iter() {
+            Self::port_ui(ui, graph, port, true, &mut commands);
+        }
+        ui.separator();
+        ui.label("outputs");
+        for port in graph.outputs.iter() {
+            Self::port_ui(ui, graph, port, false, &mut commands);
+        }
+        commands
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rules_and_skips_comments() {
+        let rules =
+            parse_rules("# playback\n\nout system:playback_.*\n  in  mic:capture_[12] \n").unwrap();
+        assert_eq!(
+            rules,
+            vec![
+                ConnectRule {
+                    direction: Direction::Out,
+                    pattern: "system:playback_.*".to_owned(),
+                },
+                ConnectRule {
+                    direction: Direction::In,
+                    pattern: "mic:capture_[12]".to_owned(),
+                },
+            ]
+        );
+        assert_eq!(rules[1].anchored(), "^(mic:capture_[12])$");
+    }
+
+    #[test]
+    fn rejects_rules_without_a_pattern() {
+        assert!(parse_rule("out").is_err());
+        assert!(parse_rule("in   ").is_err());
+        assert!(parse_rules("# fine\nout").is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_directions() {
+        assert!(parse_rule("both system:.*").is_err());
+    }
+}
@@ -0,0 +1,263 @@
+use super::common::*;
+use egui::widgets::plot::{Curve, Plot, Value};
+use num_complex::Complex32;
+use std::f32::consts::PI;
+
+const MIN_HZ: f32 = 20.0;
+// Bins below this coherence are left out of the relative plots
+const MIN_COHERENCE: f32 = 0.5;
+// Bins more than this far below the loudest bin are left out of the channel plots
+const CHANNEL_RANGE_DB: f32 = 60.0;
+
+// Removes the 2π jumps of a wrapped phase in radians.
+pub fn unwrap(phase: &[f32]) -> Vec<f32> {
+    let mut offset = 0.0;
+    let mut prev = match phase.first() {
+        Some(p) => *p,
+        None => return vec![],
+    };
+    phase
+        .iter()
+        .map(|p| {
+            let delta = p - prev;
+            if delta > PI {
+                offset -= 2.0 * PI;
+            } else if delta < -PI {
+                offset += 2.0 * PI;
+            }
+            prev = *p;
+            p + offset
+        })
+        .collect()
+}
+
+// Group delay in seconds, -dφ/dω from an unwrapped phase sampled every step Hz.
+pub fn group_delay(unwrapped: &[f32], step: f32) -> Vec<f32> {
+    let n = unwrapped.len();
+    (0..n)
+        .map(|i| {
+            let lo = i.saturating_sub(1);
+            let hi = (i + 1).min(n - 1);
+            if hi == lo {
+                return 0.0;
+            }
+            let dw = 2.0 * PI * step * (hi - lo) as f32;
+            -(unwrapped[hi] - unwrapped[lo]) / dw
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PhaseMode {
+    Channel(usize),
+    // measured channel against a reference channel
+    Relative { reference: usize, measured: usize },
+}
+
+pub struct PhaseView {
+    pub open: bool,
+    mode: PhaseMode,
+    // exponential averaging weight of each new frame for the cross spectra
+    smoothing: f32,
+    axis: Option<FreqAxis>,
+    channels: usize,
+    latest: Vec<Vec<Complex32>>,
+    g_rr: Vec<f32>,
+    g_mm: Vec<f32>,
+    g_rm: Vec<Complex32>,
+}
+
+impl Default for PhaseView {
+    fn default() -> Self {
+        Self {
+            open: false,
+            mode: PhaseMode::Relative {
+                reference: 0,
+                measured: 1,
+            },
+            smoothing: 0.2,
+            axis: None,
+            channels: DEFAULT_CHANNELS,
+            latest: vec![],
+            g_rr: vec![],
+            g_mm: vec![],
+            g_rm: vec![],
+        }
+    }
+}
+
+impl PhaseView {
+    fn reset(&mut self) {
+        self.g_rr.clear();
+        self.g_mm.clear();
+        self.g_rm.clear();
+    }
+
+    pub fn set_spectra(&mut self, frame: ComplexFrame) {
+        if self.axis != Some(frame.axis) || self.channels != frame.channels.len() {
+            self.reset();
+        }
+        self.axis = Some(frame.axis);
+        self.channels = frame.channels.len();
+
+        if let PhaseMode::Relative {
+            reference,
+            measured,
+        } = self.mode
+        {
+            let r = &frame.channels[reference.min(self.channels - 1)];
+            let m = &frame.channels[measured.min(self.channels - 1)];
+            if self.g_rm.len() != r.len() {
+                self.g_rr = vec![0.0; r.len()];
+                self.g_mm = vec![0.0; r.len()];
+                self.g_rm = vec![Complex32::new(0.0, 0.0); r.len()];
+            }
+            let a = self.smoothing;
+            for i in 0..r.len() {
+                self.g_rr[i] += a * (r[i].norm_sqr() - self.g_rr[i]);
+                self.g_mm[i] += a * (m[i].norm_sqr() - self.g_mm[i]);
+                self.g_rm[i] = self.g_rm[i] + (r[i].conj() * m[i] - self.g_rm[i]) * a;
+            }
+        }
+        self.latest = frame.channels;
+    }
+
+    // Returns whether the complex spectra are wanted, whenever that changes.
+    pub fn controls_ui(&mut self, ui: &mut egui::Ui) -> Option<bool> {
+        let was_open = self.open;
+        ui.checkbox(&mut self.open, "show");
+
+        let old_mode = self.mode;
+        let mut relative = matches!(self.mode, PhaseMode::Relative { .. });
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut relative, false, "per channel");
+            ui.radio_value(&mut relative, true, "relative");
+        });
+
+        self.mode = match (relative, self.mode) {
+            (false, PhaseMode::Channel(mut c)) => {
+                channel_combo(ui, "channel", &mut c, self.channels);
+                PhaseMode::Channel(c)
+            }
+            (false, PhaseMode::Relative { measured, .. }) => PhaseMode::Channel(measured),
+            (
+                true,
+                PhaseMode::Relative {
+                    mut reference,
+                    mut measured,
+                },
+            ) => {
+                channel_combo(ui, "reference", &mut reference, self.channels);
+                channel_combo(ui, "measured", &mut measured, self.channels);
+                PhaseMode::Relative {
+                    reference,
+                    measured,
+                }
+            }
+            (true, PhaseMode::Channel(c)) => PhaseMode::Relative {
+                reference: if c == 0 { 1 } else { 0 },
+                measured: c,
+            },
+        };
+        ui.add(egui::Slider::new(&mut self.smoothing, 0.01..=1.0).text("smoothing"));
+        if self.mode != old_mode || ui.button("reset").clicked() {
+            self.reset();
+        }
+
+        if self.open != was_open {
+            Some(self.open)
+        } else {
+            None
+        }
+    }
+
+    // Phase in radians of every bin that carries enough signal, wrapped.
+    fn phase(&self) -> Vec<(usize, f32)> {
+        match self.mode {
+            PhaseMode::Channel(c) => {
+                let spec = match self.latest.get(c) {
+                    Some(s) => s,
+                    None => return vec![],
+                };
+                let max = spec.iter().map(|x| x.norm_sqr()).fold(0.0, f32::max);
+                let floor = max * 10f32.powf(-CHANNEL_RANGE_DB / 10.0);
+                spec.iter()
+                    .enumerate()
+                    .filter(|(_, x)| x.norm_sqr() > floor)
+                    .map(|(i, x)| (i, x.arg()))
+                    .collect()
+            }
+            PhaseMode::Relative { .. } => self
+                .g_rm
+                .iter()
+                .enumerate()
+                .filter(|(i, g)| {
+                    let coherence = g.norm_sqr() / (self.g_rr[*i] * self.g_mm[*i]);
+                    coherence > MIN_COHERENCE
+                })
+                .map(|(i, g)| (i, g.arg()))
+                .collect(),
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        let axis = match self.axis {
+            Some(a) => a,
+            None => {
+                ui.label("waiting for spectra");
+                return;
+            }
+        };
+
+        let min_bin = axis.bin(MIN_HZ).max(1.0) as usize;
+        let (bins, wrapped): (Vec<usize>, Vec<f32>) = self
+            .phase()
+            .into_iter()
+            .filter(|(i, _)| *i >= min_bin)
+            .unzip();
+        // Unwrapping across skipped bins would guess the wrong number of turns, so
+        // only contiguous runs of bins are unwrapped together.
+        let mut unwrapped = vec![];
+        let mut delay = vec![];
+        let mut start = 0;
+        for end in 1..=bins.len() {
+            if end == bins.len() || bins[end] != bins[end - 1] + 1 {
+                let run = unwrap(&wrapped[start..end]);
+                delay.extend(group_delay(&run, axis.width(0)));
+                unwrapped.extend(run);
+                start = end;
+            }
+        }
+
+        let to_values = |ys: &[f32], scale: f32| -> Vec<Value> {
+            bins.iter()
+                .zip(ys.iter())
+                .map(|(i, y)| Value::new(axis.hz(*i).log10() as f64, (y * scale) as f64))
+                .collect()
+        };
+        let color = egui::Color32::from_rgb(200, 100, 100);
+        let height = 160.0;
+
+        ui.add(
+            Plot::new("phase")
+                .curve(
+                    Curve::from_values(to_values(&unwrapped, 180.0 / PI))
+                        .color(color)
+                        .name("deg / log10 Hz"),
+                )
+                .include_y(-180.0)
+                .include_y(180.0)
+                .height(height),
+        );
+        ui.add(
+            Plot::new("group delay")
+                .curve(
+                    Curve::from_values(to_values(&delay, 1000.0))
+                        .color(color)
+                        .name("ms / log10 Hz"),
+                )
+                .include_y(0.0)
+                .height(height),
+        );
+    }
+}
@@ -0,0 +1,359 @@
+use super::common::*;
+use anyhow::Result;
+use crossbeam_channel::Receiver;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+
+// Length of the fade when playback pauses, stops or starts, so it does not click.
+const FADE_SECS: f32 = 0.005;
+
+// A whole file decoded to float samples, one Vec per channel.
+#[derive(Debug)]
+pub struct AudioFile {
+    pub name: String,
+    pub sample_rate: usize,
+    pub channels: Vec<Vec<f32>>,
+}
+
+impl AudioFile {
+    pub fn len(&self) -> usize {
+        self.channels[0].len()
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.len() as f32 / self.sample_rate as f32
+    }
+}
+
+fn deinterleave(samples: Vec<f32>, channels: usize) -> Vec<Vec<f32>> {
+    let mut out = vec![Vec::with_capacity(samples.len() / channels); channels];
+    for frame in samples.chunks_exact(channels) {
+        for (c, s) in frame.iter().enumerate() {
+            out[c].push(*s);
+        }
+    }
+    out
+}
+
+fn load_wav(path: &Path) -> Result<(usize, Vec<Vec<f32>>)> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+    let samples = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<Vec<_>, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = 1.0 / (1u64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|s| s.map(|s| s as f32 * scale))
+                .collect::<Result<Vec<_>, _>>()?
+        }
+    };
+    Ok((
+        spec.sample_rate as usize,
+        deinterleave(samples, spec.channels as usize),
+    ))
+}
+
+fn load_flac(path: &Path) -> Result<(usize, Vec<Vec<f32>>)> {
+    let mut reader = claxon::FlacReader::open(path)?;
+    let info = reader.streaminfo();
+    let scale = 1.0 / (1u64 << (info.bits_per_sample - 1)) as f32;
+    let samples = reader
+        .samples()
+        .map(|s| s.map(|s| s as f32 * scale))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((
+        info.sample_rate as usize,
+        deinterleave(samples, info.channels as usize),
+    ))
+}
+
+// Decodes a WAV or FLAC file, chosen by its extension.
+pub fn load(path: &Path) -> Result<AudioFile> {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    let (sample_rate, channels) = match ext.as_deref() {
+        Some("wav") => load_wav(path)?,
+        Some("flac") => load_flac(path)?,
+        _ => bail!("{} is not a .wav or .flac file", path.display()),
+    };
+    if channels.is_empty() || channels[0].is_empty() {
+        bail!("{} holds no samples", path.display());
+    }
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    Ok(AudioFile {
+        name,
+        sample_rate,
+        channels,
+    })
+}
+
+// Where the player is, and whether it is fading in or out.
+#[derive(Debug, Clone, Copy)]
+struct Cursor {
+    playing: bool,
+    // stop once the fade out is done, and go back to the start
+    stopping: bool,
+    // position in frames of the file, fractional when resampling
+    pos: f64,
+    // where to jump to once the fade out is done, then it fades back in
+    seek_to: Option<f64>,
+    gain: f32,
+}
+
+// Plays a file from inside the Jack process callback. Files at another rate than
+// Jack are resampled with linear interpolation, which is fine for listening and
+// rough analysis but rolls off the top octave a little.
+pub struct Player {
+    file: Option<Arc<AudioFile>>,
+    sample_rate: usize,
+    looping: bool,
+    cursor: Cursor,
+    step: f64,
+    fade: f32,
+    playhead: Playhead,
+}
+
+impl Player {
+    pub fn new(sample_rate: usize, playhead: Playhead) -> Player {
+        Player {
+            file: None,
+            sample_rate,
+            looping: false,
+            cursor: Cursor {
+                playing: false,
+                stopping: false,
+                pos: 0.0,
+                seek_to: None,
+                gain: 0.0,
+            },
+            step: 1.0,
+            fade: 1.0 / (FADE_SECS * sample_rate as f32),
+            playhead,
+        }
+    }
+
+    // Keeps the pitch and fade times when Jack changes its sample rate.
+    pub fn set_sample_rate(&mut self, sample_rate: usize) {
+        self.sample_rate = sample_rate;
+        self.fade = 1.0 / (FADE_SECS * sample_rate as f32);
+        if let Some(file) = &self.file {
+            self.step = file.sample_rate as f64 / sample_rate as f64;
+        }
+    }
+
+    pub fn command(&mut self, command: PlayerCommand) {
+        let cursor = &mut self.cursor;
+        match command {
+            PlayerCommand::Load(file) => {
+                self.step = file.sample_rate as f64 / self.sample_rate as f64;
+                self.file = Some(file);
+                cursor.playing = false;
+                cursor.stopping = false;
+                cursor.gain = 0.0;
+                cursor.pos = 0.0;
+                cursor.seek_to = None;
+            }
+            PlayerCommand::Play => {
+                cursor.playing = self.file.is_some();
+                cursor.stopping = false;
+            }
+            PlayerCommand::Pause => cursor.playing = false,
+            PlayerCommand::Stop => {
+                cursor.playing = false;
+                cursor.stopping = true;
+                cursor.seek_to = None;
+            }
+            PlayerCommand::Seek(frame) if cursor.gain == 0.0 => cursor.pos = frame as f64,
+            PlayerCommand::Seek(frame) => cursor.seek_to = Some(frame as f64),
+            PlayerCommand::Looping(on) => self.looping = on,
+        }
+    }
+
+    // Fills one buffer per output. Mono files go to every output, channels beyond
+    // the number of outputs are left out. Each output is rendered from the same
+    // starting point, so they stay in step.
+    pub fn fill<'b>(&mut self, outs: impl Iterator<Item = &'b mut [f32]>) {
+        let start = self.cursor;
+        for (c, out) in outs.enumerate() {
+            self.cursor = start;
+            self.render(c, out);
+        }
+        self.playhead
+            .store(self.cursor.pos as u64, Ordering::Release);
+    }
+
+    fn render(&mut self, channel: usize, out: &mut [f32]) {
+        let file = match &self.file {
+            Some(f) => f,
+            None => {
+                out.iter_mut().for_each(|s| *s = 0.0);
+                return;
+            }
+        };
+        let ch = &file.channels[channel.min(file.channels.len() - 1)];
+        let len = ch.len();
+        let cursor = &mut self.cursor;
+
+        for y in out.iter_mut() {
+            if cursor.playing && cursor.seek_to.is_none() {
+                cursor.gain = (cursor.gain + self.fade).min(1.0);
+            } else {
+                cursor.gain = (cursor.gain - self.fade).max(0.0);
+            }
+            if cursor.gain == 0.0 {
+                if cursor.stopping {
+                    cursor.stopping = false;
+                    cursor.pos = 0.0;
+                }
+                if let Some(pos) = cursor.seek_to.take() {
+                    cursor.pos = pos;
+                }
+                *y = 0.0;
+                continue;
+            }
+
+            let at = cursor.pos.floor() as usize;
+            let frac = (cursor.pos - at as f64) as f32;
+            let next = if at + 1 < len {
+                at + 1
+            } else if self.looping {
+                0
+            } else {
+                at
+            };
+            *y = cursor.gain * (ch[at] + frac * (ch[next] - ch[at]));
+
+            cursor.pos += self.step;
+            if cursor.pos >= len as f64 {
+                if self.looping {
+                    cursor.pos -= len as f64;
+                } else {
+                    cursor.playing = false;
+                    cursor.gain = 0.0;
+                    cursor.pos = 0.0;
+                }
+            }
+        }
+    }
+}
+
+pub struct PlayerView {
+    path: String,
+    looping: bool,
+    status: String,
+    // Held so a file is never freed from inside the Jack process callback: the
+    // player drops the previous file when the next one arrives, the view keeps it
+    // retired until then.
+    file: Option<Arc<AudioFile>>,
+    retired: Vec<Arc<AudioFile>>,
+    loading: Option<Receiver<Result<AudioFile>>>,
+    playhead: Playhead,
+}
+
+impl PlayerView {
+    pub fn new(playhead: Playhead) -> PlayerView {
+        PlayerView {
+            path: "reference.wav".to_owned(),
+            looping: true,
+            status: String::new(),
+            file: None,
+            retired: vec![],
+            loading: None,
+            playhead,
+        }
+    }
+
+    // Takes the file off the loader thread once it is decoded.
+    fn poll_loading(&mut self) -> Option<Arc<AudioFile>> {
+        let result = self.loading.as_ref()?.try_recv().ok()?;
+        self.loading = None;
+        match result {
+            Ok(file) => {
+                self.status = format!(
+                    "{}: {} ch, {} Hz, {:.1} s",
+                    file.name,
+                    file.channels.len(),
+                    file.sample_rate,
+                    file.duration()
+                );
+                let file = Arc::new(file);
+                self.retired.extend(self.file.replace(file.clone()));
+                Some(file)
+            }
+            Err(e) => {
+                self.status = format!("load failed: {}", e);
+                None
+            }
+        }
+    }
+
+    // Returns the transport commands for the player.
+    pub fn controls_ui(&mut self, ui: &mut egui::Ui, sample_rate: usize) -> Vec<PlayerCommand> {
+        // only this view still holds them once the player has moved on
+        self.retired.retain(|f| Arc::strong_count(f) > 1);
+
+        let mut commands = vec![];
+        if let Some(file) = self.poll_loading() {
+            commands.push(PlayerCommand::Load(file));
+            commands.push(PlayerCommand::Looping(self.looping));
+        }
+
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.path);
+            if ui.button("Load").clicked() && self.loading.is_none() {
+                let (tx, rx) = crossbeam_channel::bounded(1);
+                let path = self.path.clone();
+                thread::spawn(move || tx.send(load(Path::new(&path))).ok());
+                self.loading = Some(rx);
+                self.status = "loading...".to_owned();
+            }
+        });
+        if self.loading.is_some() {
+            ui.ctx().request_repaint();
+        }
+
+        let file = match &self.file {
+            Some(f) => f.clone(),
+            None => {
+                ui.label(&self.status);
+                return commands;
+            }
+        };
+        ui.horizontal(|ui| {
+            if ui.button("Play").clicked() {
+                commands.push(PlayerCommand::Play);
+            }
+            if ui.button("Pause").clicked() {
+                commands.push(PlayerCommand::Pause);
+            }
+            if ui.button("Stop").clicked() {
+                commands.push(PlayerCommand::Stop);
+            }
+            if ui.checkbox(&mut self.looping, "loop").changed() {
+                commands.push(PlayerCommand::Looping(self.looping));
+            }
+        });
+
+        let mut secs = self.playhead.load(Ordering::Acquire) as f32 / file.sample_rate as f32;
+        if ui
+            .add(egui::Slider::new(&mut secs, 0.0..=file.duration()).text("s"))
+            .changed()
+        {
+            let frame = (secs * file.sample_rate as f32) as usize;
+            commands.push(PlayerCommand::Seek(frame.min(file.len() - 1)));
+        }
+        ui.label(&self.status);
+        if file.sample_rate != sample_rate {
+            ui.label(format!("resampled from {} Hz", file.sample_rate));
+        }
+        commands
+    }
+}
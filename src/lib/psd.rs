@@ -0,0 +1,344 @@
+use super::common::*;
+use realfft::{RealFftPlanner, RealToComplex};
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WelchParams {
+    // Samples per periodogram, consecutive segments overlap by half
+    pub segment: usize,
+    // Periodograms averaged into each reported frame
+    pub averages: usize,
+}
+
+impl Default for WelchParams {
+    fn default() -> Self {
+        WelchParams {
+            segment: 4096,
+            averages: 16,
+        }
+    }
+}
+
+pub fn hann(len: usize) -> Vec<f32> {
+    let m = (len - 1) as f32;
+    (0..len)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / m).cos())
+        .collect()
+}
+
+// 4 term Blackman-Harris window, sidelobes below -92 dB for measuring small
+// products next to large tones.
+pub fn blackman_harris(len: usize) -> Vec<f32> {
+    let m = (len - 1) as f32;
+    (0..len)
+        .map(|i| {
+            let x = 2.0 * PI * i as f32 / m;
+            0.35875 - 0.48829 * x.cos() + 0.14128 * (2.0 * x).cos() - 0.01168 * (3.0 * x).cos()
+        })
+        .collect()
+}
+
+// Equivalent noise bandwidth of a window in Hz.
+pub fn enbw(window: &[f32], sample_rate: usize) -> f32 {
+    let sum: f32 = window.iter().sum();
+    let sum_sq: f32 = window.iter().map(|w| w * w).sum();
+    sample_rate as f32 * sum_sq / (sum * sum)
+}
+
+// Welch power spectral density estimate. Samples are pushed as they arrive and a
+// one-sided density in FS²/Hz comes out once enough segments have been averaged.
+pub struct Welch {
+    params: WelchParams,
+    sample_rate: usize,
+    window: Vec<f32>,
+    fft: Arc<dyn RealToComplex<f32>>,
+    pending: Vec<f32>,
+    in_buf: Vec<f32>,
+    spec_buf: Vec<num_complex::Complex32>,
+    acc: Vec<f32>,
+    count: usize,
+    // Turns |X|² into a density: the power of a bin spread over the window's ENBW
+    scale: f32,
+}
+
+impl Welch {
+    pub fn new(params: WelchParams, sample_rate: usize) -> Welch {
+        let window = hann(params.segment);
+        let mut planner = RealFftPlanner::<f32>::new();
+        let fft = planner.plan_fft_forward(params.segment);
+        let sum: f32 = window.iter().sum();
+
+        Welch {
+            params,
+            sample_rate,
+            in_buf: fft.make_input_vec(),
+            spec_buf: fft.make_output_vec(),
+            acc: vec![0.0; params.segment / 2 + 1],
+            count: 0,
+            pending: Vec::with_capacity(params.segment * 2),
+            scale: 1.0 / (sum * sum * enbw(&window, sample_rate)),
+            window,
+            fft,
+        }
+    }
+
+    pub fn push(&mut self, samples: &[f32]) -> Vec<Frame> {
+        self.pending.extend_from_slice(samples);
+
+        let segment = self.params.segment;
+        let hop = segment / 2;
+        let mut frames = vec![];
+        while self.pending.len() >= segment {
+            for i in 0..segment {
+                self.in_buf[i] = self.pending[i] * self.window[i];
+            }
+            self.fft
+                .process(&mut self.in_buf, &mut self.spec_buf)
+                .unwrap();
+            for (a, x) in self.acc.iter_mut().zip(self.spec_buf.iter()) {
+                *a += x.norm_sqr();
+            }
+            self.pending.drain(0..hop);
+            self.count += 1;
+
+            if self.count == self.params.averages {
+                frames.push(self.frame());
+            }
+        }
+        frames
+    }
+
+    fn frame(&mut self) -> Frame {
+        let last = self.acc.len() - 1;
+        let norm = self.scale / self.count as f32;
+        // one-sided, everything but DC and Nyquist gets the power of its negative twin
+        let bins = self
+            .acc
+            .iter()
+            .enumerate()
+            .map(|(i, a)| {
+                let side = if i == 0 || i == last { 1.0 } else { 2.0 };
+                a * norm * side
+            })
+            .collect();
+
+        self.acc.iter_mut().for_each(|a| *a = 0.0);
+        self.count = 0;
+
+        Frame {
+            bins,
+            axis: FreqAxis::Linear {
+                start: 0.0,
+                step: self.sample_rate as f32 / self.params.segment as f32,
+            },
+            scale: Scale::Density,
+            period: (self.params.segment / 2 * self.params.averages) as f32
+                / self.sample_rate as f32,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MultitaperParams {
+    // Samples per estimate
+    pub segment: usize,
+    // Time-bandwidth product NW, the half bandwidth is NW / N cycles per sample
+    pub bandwidth: f32,
+    // Number of tapers averaged, at most 2NW - 1 of them are well concentrated
+    pub tapers: usize,
+}
+
+impl Default for MultitaperParams {
+    fn default() -> Self {
+        MultitaperParams {
+            segment: 2048,
+            bandwidth: 4.0,
+            tapers: 7,
+        }
+    }
+}
+
+impl MultitaperParams {
+    // Half bandwidth in Hz, the frequency resolution of the estimate.
+    pub fn half_bandwidth(&self, sample_rate: usize) -> f32 {
+        self.bandwidth * sample_rate as f32 / self.segment as f32
+    }
+}
+
+// Number of eigenvalues of the symmetric tridiagonal matrix below x, by Sturm sequence.
+fn eigenvalues_below(diag: &[f64], off: &[f64], x: f64) -> usize {
+    let mut count = 0;
+    let mut q = 1.0;
+    for i in 0..diag.len() {
+        let e2 = if i == 0 { 0.0 } else { off[i - 1] * off[i - 1] };
+        q = diag[i] - x - e2 / q;
+        if q == 0.0 {
+            q = -f64::EPSILON;
+        }
+        if q < 0.0 {
+            count += 1;
+        }
+    }
+    count
+}
+
+// Solves (T - shift) y = x for the symmetric tridiagonal T, overwriting x with y.
+fn tridiagonal_solve(diag: &[f64], off: &[f64], shift: f64, x: &mut [f64]) {
+    let n = diag.len();
+    let mut c = vec![0.0; n];
+    let mut b = diag[0] - shift;
+    c[0] = if n > 1 { off[0] / b } else { 0.0 };
+    x[0] /= b;
+    for i in 1..n {
+        b = diag[i] - shift - off[i - 1] * c[i - 1];
+        if i < n - 1 {
+            c[i] = off[i] / b;
+        }
+        x[i] = (x[i] - off[i - 1] * x[i - 1]) / b;
+    }
+    for i in (0..n - 1).rev() {
+        x[i] -= c[i] * x[i + 1];
+    }
+}
+
+// The count best concentrated discrete prolate spheroidal (Slepian) sequences of
+// length len and time-bandwidth product nw, each with unit energy. They are the
+// eigenvectors of a tridiagonal matrix that commutes with the concentration
+// problem, found by bisection and inverse iteration.
+pub fn dpss(len: usize, nw: f32, count: usize) -> Vec<Vec<f32>> {
+    let w = nw as f64 / len as f64;
+    let c = (2.0 * std::f64::consts::PI * w).cos();
+    let diag: Vec<f64> = (0..len)
+        .map(|i| {
+            let x = (len - 1) as f64 / 2.0 - i as f64;
+            x * x * c
+        })
+        .collect();
+    let off: Vec<f64> = (1..len).map(|i| (i * (len - i)) as f64 / 2.0).collect();
+
+    let bound = (0..len)
+        .map(|i| {
+            let left = if i > 0 { off[i - 1] } else { 0.0 };
+            let right = if i < len - 1 { off[i] } else { 0.0 };
+            diag[i].abs() + left + right
+        })
+        .fold(0.0, f64::max);
+
+    (0..count.min(len))
+        .map(|k| {
+            // the kth largest eigenvalue
+            let index = len - 1 - k;
+            let (mut lo, mut hi) = (-bound, bound);
+            for _ in 0..200 {
+                let mid = (lo + hi) / 2.0;
+                if eigenvalues_below(&diag, &off, mid) > index {
+                    hi = mid;
+                } else {
+                    lo = mid;
+                }
+            }
+            let lambda = (lo + hi) / 2.0;
+
+            // a slightly shifted eigenvalue keeps the solve from dividing by zero
+            let shift = lambda + bound * 1e-12;
+            let mut v: Vec<f64> = (0..len).map(|i| 1.0 + i as f64 / len as f64).collect();
+            for _ in 0..3 {
+                tridiagonal_solve(&diag, &off, shift, &mut v);
+                let norm = v.iter().map(|x| x * x).sum::<f64>().sqrt();
+                v.iter_mut().for_each(|x| *x /= norm);
+            }
+            // even tapers start out positive on average, odd ones rising
+            let sign: f64 = if k % 2 == 0 {
+                v.iter().sum()
+            } else {
+                v.iter()
+                    .enumerate()
+                    .map(|(i, x)| (len as f64 / 2.0 - i as f64) * x)
+                    .sum()
+            };
+            let sign = if sign < 0.0 { -1.0 } else { 1.0 };
+            v.iter().map(|x| (x * sign) as f32).collect()
+        })
+        .collect()
+}
+
+// Multitaper power spectral density: the periodograms of one segment under several
+// orthogonal tapers are averaged, which lowers the variance without the long record
+// Welch averaging needs. Every segment gives a one-sided density in FS²/Hz.
+pub struct Multitaper {
+    sample_rate: usize,
+    tapers: Vec<Vec<f32>>,
+    pending: Vec<f32>,
+    fft: Arc<dyn RealToComplex<f32>>,
+    in_buf: Vec<f32>,
+    spec_buf: Vec<num_complex::Complex32>,
+    acc: Vec<f32>,
+}
+
+impl Multitaper {
+    pub fn new(params: MultitaperParams, sample_rate: usize) -> Multitaper {
+        let len = params.segment;
+        let mut planner = RealFftPlanner::<f32>::new();
+        let fft = planner.plan_fft_forward(len);
+        Multitaper {
+            sample_rate,
+            tapers: dpss(len, params.bandwidth, params.tapers.max(1)),
+            pending: Vec::with_capacity(len * 2),
+            in_buf: fft.make_input_vec(),
+            spec_buf: fft.make_output_vec(),
+            acc: vec![0.0; len / 2 + 1],
+            fft,
+        }
+    }
+
+    pub fn push(&mut self, samples: &[f32]) -> Vec<Frame> {
+        self.pending.extend_from_slice(samples);
+        let len = self.in_buf.len();
+        let mut frames = vec![];
+        while self.pending.len() >= len {
+            frames.push(self.frame());
+            self.pending.drain(0..len);
+        }
+        frames
+    }
+
+    fn frame(&mut self) -> Frame {
+        let len = self.in_buf.len();
+        self.acc.iter_mut().for_each(|a| *a = 0.0);
+        for taper in self.tapers.iter() {
+            for i in 0..len {
+                self.in_buf[i] = self.pending[i] * taper[i];
+            }
+            self.fft
+                .process(&mut self.in_buf, &mut self.spec_buf)
+                .unwrap();
+            for (a, x) in self.acc.iter_mut().zip(self.spec_buf.iter()) {
+                *a += x.norm_sqr();
+            }
+        }
+
+        // unit energy tapers make |X|² / sample rate a two-sided density
+        let last = self.acc.len() - 1;
+        let norm = 1.0 / (self.tapers.len() as f32 * self.sample_rate as f32);
+        let bins = self
+            .acc
+            .iter()
+            .enumerate()
+            .map(|(i, a)| {
+                let side = if i == 0 || i == last { 1.0 } else { 2.0 };
+                a * norm * side
+            })
+            .collect();
+
+        Frame {
+            bins,
+            axis: FreqAxis::Linear {
+                start: 0.0,
+                step: self.sample_rate as f32 / len as f32,
+            },
+            scale: Scale::Density,
+            period: len as f32 / self.sample_rate as f32,
+        }
+    }
+}
@@ -0,0 +1,272 @@
+use super::common::*;
+
+const DIVS_X: usize = 10;
+const DIVS_Y: usize = 8;
+const MAX_TIMEBASE_MS: f32 = 200.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Edge {
+    Rising,
+    Falling,
+}
+
+pub struct ScopeView {
+    pub open: bool,
+    source: usize,
+    edge: Edge,
+    level: f32,
+    holdoff_ms: f32,
+    // fraction of the screen shown before the trigger point
+    pre_trigger: f32,
+    timebase_ms: f32,
+    // full scale per vertical division
+    volts_per_div: f32,
+    // free run when there is no trigger
+    auto: bool,
+    // trigger on the generator's burst starts instead of an edge
+    sync_trigger: bool,
+    // absolute sample index of a burst start waiting for enough samples after it
+    pending_sync: Option<u64>,
+    sample_rate: usize,
+    history: Vec<Vec<f32>>,
+    // absolute sample index of history[_][0]
+    history_start: u64,
+    // earliest absolute sample index the next trigger may occur at
+    next_allowed: u64,
+    // samples since the last trigger, for auto mode
+    since_trigger: usize,
+    traces: Vec<Vec<f32>>,
+    // index of the trigger point in traces, None when free running
+    trigger_at: Option<usize>,
+}
+
+impl Default for ScopeView {
+    fn default() -> Self {
+        Self {
+            open: false,
+            source: 0,
+            edge: Edge::Rising,
+            level: 0.0,
+            holdoff_ms: 0.0,
+            pre_trigger: 0.1,
+            timebase_ms: 1.0,
+            volts_per_div: 0.25,
+            auto: true,
+            sync_trigger: false,
+            pending_sync: None,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            history: vec![],
+            history_start: 0,
+            next_allowed: 0,
+            since_trigger: 0,
+            traces: vec![],
+            trigger_at: None,
+        }
+    }
+}
+
+impl ScopeView {
+    fn window(&self) -> usize {
+        ((self.timebase_ms * DIVS_X as f32 / 1000.0 * self.sample_rate as f32) as usize).max(2)
+    }
+
+    fn crossed(&self, a: f32, b: f32) -> bool {
+        match self.edge {
+            Edge::Rising => a < self.level && b >= self.level,
+            Edge::Falling => a > self.level && b <= self.level,
+        }
+    }
+
+    pub fn push(&mut self, block: &SampleBlock, sample_rate: usize) {
+        let channels = &block.channels;
+        if self.history.len() != channels.len() || self.sample_rate != sample_rate {
+            self.history = vec![vec![]; channels.len()];
+            self.sample_rate = sample_rate;
+        }
+        let searched_to = self.history_start + self.history[0].len() as u64;
+        for (h, b) in self.history.iter_mut().zip(channels.iter()) {
+            h.extend_from_slice(b);
+        }
+        if let Some(offset) = block.sync {
+            self.pending_sync = Some(searched_to + offset as u64);
+        }
+
+        let window = self.window();
+        // at least one sample after the trigger, the edge search looks at trace[t]
+        let pre = ((self.pre_trigger * window as f32) as usize).min(window - 1);
+        let post = window - pre;
+        let len = self.history[0].len();
+        let holdoff = (self.holdoff_ms / 1000.0 * self.sample_rate as f32) as u64;
+        let source = self.source.min(self.history.len() - 1);
+
+        // Look for edges in the new samples plus the ones that were too close to the
+        // end of the history to show a full screen last time around.
+        let from =
+            (searched_to.saturating_sub(self.history_start + post as u64) as usize).max(pre.max(1));
+        let mut found = None;
+        if self.sync_trigger {
+            if let Some(abs) = self.pending_sync {
+                if abs < self.history_start + pre as u64 {
+                    // too old to show what came before it
+                    self.pending_sync = None;
+                } else if (abs - self.history_start) as usize + post <= len {
+                    found = Some((abs - self.history_start) as usize);
+                    self.pending_sync = None;
+                }
+            }
+        } else if len >= post {
+            let trace = &self.history[source];
+            for t in from..=(len - post) {
+                let abs = self.history_start + t as u64;
+                if abs >= self.next_allowed && self.crossed(trace[t - 1], trace[t]) {
+                    found = Some(t);
+                    self.next_allowed = abs + holdoff.max(window as u64);
+                }
+            }
+        }
+
+        match found {
+            Some(t) => {
+                self.traces = self
+                    .history
+                    .iter()
+                    .map(|h| h[t - pre..t + post].to_vec())
+                    .collect();
+                self.trigger_at = Some(pre);
+                self.since_trigger = len - t;
+            }
+            None => {
+                self.since_trigger += channels[0].len();
+                if self.auto && self.since_trigger > window * 2 && len >= window {
+                    self.traces = self
+                        .history
+                        .iter()
+                        .map(|h| h[len - window..].to_vec())
+                        .collect();
+                    self.trigger_at = None;
+                }
+            }
+        }
+
+        // keep enough for a full screen around a trigger at the very end
+        let keep = window * 2;
+        if len > keep {
+            let drop = len - keep;
+            self.history.iter_mut().for_each(|h| {
+                h.drain(0..drop);
+            });
+            self.history_start += drop as u64;
+        }
+    }
+
+    pub fn controls_ui(&mut self, ui: &mut egui::Ui, channels: usize) {
+        ui.checkbox(&mut self.open, "show");
+        channel_combo(ui, "trigger source", &mut self.source, channels);
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut self.edge, Edge::Rising, "rising");
+            ui.radio_value(&mut self.edge, Edge::Falling, "falling");
+            ui.checkbox(&mut self.auto, "auto");
+        });
+        ui.checkbox(&mut self.sync_trigger, "trigger on generator bursts");
+        ui.add(egui::Slider::new(&mut self.level, -1.0..=1.0).text("level"));
+        ui.add(egui::Slider::new(&mut self.holdoff_ms, 0.0..=1000.0).text("holdoff ms"));
+        ui.add(egui::Slider::new(&mut self.pre_trigger, 0.0..=1.0).text("pre-trigger"));
+        ui.add(
+            egui::Slider::new(&mut self.timebase_ms, 0.01..=MAX_TIMEBASE_MS)
+                .logarithmic(true)
+                .text("ms/div"),
+        );
+        ui.add(
+            egui::Slider::new(&mut self.volts_per_div, 0.001..=1.0)
+                .logarithmic(true)
+                .text("FS/div"),
+        );
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        let width = ui.available_width();
+        let size = egui::vec2(width, width * DIVS_Y as f32 / DIVS_X as f32);
+        let (response, painter) = ui.allocate_painter(size, egui::Sense::hover());
+        let rect = response.rect;
+
+        painter.rect_filled(rect, 0.0, egui::Color32::from_gray(10));
+        let grid = egui::Stroke::new(1.0, egui::Color32::from_gray(50));
+        for i in 0..=DIVS_X {
+            let x = rect.left() + rect.width() * i as f32 / DIVS_X as f32;
+            painter.line_segment(
+                [egui::pos2(x, rect.top()), egui::pos2(x, rect.bottom())],
+                grid,
+            );
+        }
+        for i in 0..=DIVS_Y {
+            let y = rect.top() + rect.height() * i as f32 / DIVS_Y as f32;
+            painter.line_segment(
+                [egui::pos2(rect.left(), y), egui::pos2(rect.right(), y)],
+                grid,
+            );
+        }
+
+        let full_scale = self.volts_per_div * DIVS_Y as f32 / 2.0;
+        let to_y =
+            |v: f32| rect.center().y - (v / full_scale).clamp(-1.0, 1.0) * rect.height() / 2.0;
+
+        let marker = egui::Stroke::new(1.0, egui::Color32::from_rgb(220, 200, 100));
+        let level_y = to_y(self.level);
+        painter.line_segment(
+            [
+                egui::pos2(rect.left(), level_y),
+                egui::pos2(rect.left() + 8.0, level_y),
+            ],
+            marker,
+        );
+
+        for (c, trace) in self.traces.iter().enumerate() {
+            if trace.len() < 2 {
+                continue;
+            }
+            let dx = rect.width() / (trace.len() - 1) as f32;
+            // Long traces are drawn as the min and max of each pixel column
+            let step = (trace.len() as f32 / rect.width()).ceil().max(1.0) as usize;
+            let points = if step == 1 {
+                trace
+                    .iter()
+                    .enumerate()
+                    .map(|(i, v)| egui::pos2(rect.left() + i as f32 * dx, to_y(*v)))
+                    .collect()
+            } else {
+                let mut points = vec![];
+                for (n, chunk) in trace.chunks(step).enumerate() {
+                    let x = rect.left() + (n * step) as f32 * dx;
+                    let min = chunk.iter().cloned().fold(f32::MAX, f32::min);
+                    let max = chunk.iter().cloned().fold(f32::MIN, f32::max);
+                    points.push(egui::pos2(x, to_y(min)));
+                    points.push(egui::pos2(x, to_y(max)));
+                }
+                points
+            };
+            painter.add(egui::Shape::line(
+                points,
+                egui::Stroke::new(1.0, channel_color(c)),
+            ));
+        }
+
+        if let (Some(t), Some(trace)) = (self.trigger_at, self.traces.first()) {
+            let x = rect.left() + rect.width() * t as f32 / (trace.len() - 1) as f32;
+            painter.line_segment(
+                [egui::pos2(x, rect.top()), egui::pos2(x, rect.top() + 8.0)],
+                marker,
+            );
+        }
+
+        ui.label(format!(
+            "{} ms/div, {} FS/div{}",
+            self.timebase_ms,
+            self.volts_per_div,
+            if self.trigger_at.is_none() {
+                ", untriggered"
+            } else {
+                ""
+            }
+        ));
+    }
+}
@@ -68,16 +68,90 @@ const FRAGMENT_SHADER: &'static str = r#"
     // Uniform parameter passed in from the frame.draw() call.
     uniform sampler2D data_tex;
     uniform sampler1D color_tex;
+    uniform sampler2D mark_tex;
 
     float data_val;
+    vec4 mark;
 
     void main() {
         // Applies a texture to the rectangle.
         data_val = texture(data_tex, v_tex_coords).s;
-        color = texture(color_tex, data_val);
+        // Marks are drawn over the data wherever they are not transparent.
+        mark = texture(mark_tex, v_tex_coords);
+        color = mix(texture(color_tex, data_val), vec4(mark.rgb, 1.0), mark.a);
     }
 "#;
 
+pub const AXIS_HEIGHT: f32 = 18.0;
+// Half width in texels of a mark
+const MARK_RADIUS: usize = 8;
+
+// Position of a frequency across a row spanning min_hz to max_hz, 0 to 1.
+pub fn position(hz: f32, min_hz: f32, max_hz: f32, log: bool) -> f32 {
+    if log {
+        (hz / min_hz).ln() / (max_hz / min_hz).ln()
+    } else {
+        (hz - min_hz) / (max_hz - min_hz)
+    }
+}
+
+// Frequency labels for the columns of the spectrograph, drawn into a strip below it
+// since anything egui paints over the spectrograph itself is hidden. Log axes are
+// labelled at 1, 2 and 5 times each power of ten.
+pub fn axis_ui(ui: &mut egui::Ui, min_hz: f32, max_hz: f32, log: bool) {
+    let size = egui::vec2(ui.available_width(), AXIS_HEIGHT);
+    let (response, painter) = ui.allocate_painter(size, egui::Sense::hover());
+    let rect = response.rect;
+    if max_hz <= min_hz || (log && min_hz <= 0.0) {
+        return;
+    }
+
+    let ticks: Vec<f32> = if log {
+        let mut decade = 10f32.powf(min_hz.log10().floor());
+        let mut ticks = vec![];
+        while decade <= max_hz {
+            ticks.extend([1.0, 2.0, 5.0].iter().map(|m| m * decade));
+            decade *= 10.0;
+        }
+        ticks
+    } else {
+        // 1, 2 or 5 times a power of ten, for about ten ticks across
+        let raw = (max_hz - min_hz) / 10.0;
+        let decade = 10f32.powf(raw.log10().floor());
+        let step = [1.0, 2.0, 5.0, 10.0]
+            .iter()
+            .map(|m| m * decade)
+            .find(|s| *s >= raw)
+            .unwrap();
+        let first = (min_hz / step).ceil() as i64;
+        let last = (max_hz / step).floor() as i64;
+        (first..=last).map(|i| i as f32 * step).collect()
+    };
+    let color = egui::Color32::from_gray(160);
+    for hz in ticks
+        .into_iter()
+        .filter(|hz| *hz >= min_hz && *hz <= max_hz)
+    {
+        let x = rect.left() + position(hz, min_hz, max_hz, log) * rect.width();
+        let label = if hz >= 1000.0 {
+            format!("{}k", hz / 1000.0)
+        } else {
+            format!("{}", (hz * 1000.0).round() / 1000.0)
+        };
+        painter.line_segment(
+            [egui::pos2(x, rect.top()), egui::pos2(x, rect.top() + 4.0)],
+            egui::Stroke::new(1.0, color),
+        );
+        painter.text(
+            egui::pos2(x, rect.top() + 4.0),
+            egui::Align2::CENTER_TOP,
+            label,
+            egui::TextStyle::Small,
+            color,
+        );
+    }
+}
+
 const SCREEN_WIDTH: u32 = 1024;
 const SCREEN_HEIGHT: u32 = 768;
 
@@ -90,6 +164,8 @@ pub struct Spectrograph {
     tex_height: u32,
     data_texture: glium::texture::texture2d::Texture2d,
     color_texture: glium::texture::srgb_texture1d::SrgbTexture1d,
+    // RGBA marks drawn over the data, scrolled along with it
+    mark_texture: glium::texture::texture2d::Texture2d,
     vertex_position: egui::Rect,
     rect_vertices: glium::VertexBuffer<Vertex>,
     rect_indices: glium::IndexBuffer<u16>,
@@ -139,6 +215,29 @@ impl Spectrograph {
             data_image,
         );
 
+        let mark_texture = glium::texture::texture2d::Texture2d::empty_with_format(
+            display,
+            glium::texture::UncompressedFloatFormat::U8U8U8U8,
+            mipmap,
+            tex_width,
+            tex_height,
+        )
+        .unwrap();
+        mark_texture.write(
+            glium::Rect {
+                left: 0,
+                bottom: 0,
+                width: tex_width,
+                height: tex_height,
+            },
+            glium::texture::RawImage2d {
+                data: Cow::from(vec![0u8; (tex_width * tex_height * 4) as usize]),
+                width: tex_width,
+                height: tex_height,
+                format: glium::texture::ClientFormat::U8U8U8U8,
+            },
+        );
+
         let (rect_vertices, rect_indices) = {
             let ib_data: Vec<u16> = vec![0, 1, 2, 1, 3, 2];
             let vb = glium::VertexBuffer::empty_dynamic(display, 4).unwrap();
@@ -167,6 +266,7 @@ impl Spectrograph {
             height,
             data_texture,
             color_texture,
+            mark_texture,
             rect_program,
             rect_vertices,
             rect_indices,
@@ -176,7 +276,35 @@ impl Spectrograph {
         }
     }
 
-    pub fn update(&mut self, data: Vec<f32>) {
+    // Adds a row of data in 0..1. Marks are positions across the row in 0..1 with the
+    // color to draw there.
+    pub fn update(&mut self, data: Vec<f32>, marks: &[(f32, egui::Color32)]) {
+        let mut mark_row = vec![0u8; self.tex_width as usize * 4];
+        let last = self.tex_width as usize - 1;
+        for (pos, color) in marks {
+            if *pos < 0.0 || *pos > 1.0 {
+                continue;
+            }
+            let center = (pos * last as f32).round() as usize;
+            for x in center.saturating_sub(MARK_RADIUS)..=(center + MARK_RADIUS).min(last) {
+                mark_row[x * 4..x * 4 + 4].copy_from_slice(&color.to_array());
+            }
+        }
+        self.mark_texture.write(
+            glium::Rect {
+                left: 0,
+                bottom: self.offset_idx,
+                width: self.tex_width,
+                height: 1,
+            },
+            glium::texture::RawImage2d {
+                data: Cow::from(mark_row),
+                width: self.tex_width,
+                height: 1,
+                format: glium::texture::ClientFormat::U8U8U8U8,
+            },
+        );
+
         self.offset = ((self.offset_idx + 1) as f32) / (self.height as f32);
         self.data_texture.write(
             glium::Rect {
@@ -226,6 +354,9 @@ impl Spectrograph {
             color_tex: glium::uniforms::Sampler::new(&self.color_texture)
                 .magnify_filter(glium::uniforms::MagnifySamplerFilter::Linear)
                 .wrap_function(glium::uniforms::SamplerWrapFunction::Clamp),
+            mark_tex: glium::uniforms::Sampler::new(&self.mark_texture)
+                .magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest)
+                .wrap_function(glium::uniforms::SamplerWrapFunction::Repeat),
             offset: self.offset,
 
         };
@@ -1,38 +1,518 @@
-use egui::widgets::plot::{Curve, Plot};
+use super::common::*;
+use super::cwt::CwtParams;
+use super::multires::{MultiResParams, TIERS};
+use super::psd::{enbw, hann, MultitaperParams, WelchParams};
+use super::zoom::ZoomParams;
+use egui::widgets::plot::{Curve, Plot, Value};
 
-#[derive(PartialEq)]
-pub struct Spectrum {}
+const WELCH_SEGMENTS: [usize; 8] = [512, 1024, 2048, 4096, 8192, 16384, 32768, 65536];
+const MULTITAPER_SEGMENTS: [usize; 6] = [256, 512, 1024, 2048, 4096, 8192];
+const ZOOM_SPANS: [f32; 10] = [1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0, 1000.0];
+const ZOOM_SIZES: [usize; 5] = [128, 256, 512, 1024, 2048];
+const MULTIRES_SIZES: [usize; 8] = [256, 512, 1024, 2048, 4096, 8192, 16384, 32768];
+// Range of the density display, mapped onto the spectrograph colors
+const DENSITY_MIN_DB: f32 = -160.0;
+const DENSITY_MAX_DB: f32 = -40.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DensityUnit {
+    DbfsPerRtHz,
+    VoltsSqPerHz,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ModeKind {
+    Spectrum,
+    Psd,
+    Multitaper,
+    Zoom,
+    MultiRes,
+    Cwt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Weighting {
+    Z,
+    A,
+    C,
+}
+
+impl Weighting {
+    // IEC 61672 frequency weightings as linear amplitude gains.
+    pub fn gain(&self, hz: f32) -> f32 {
+        let f2 = (hz as f64).powi(2);
+        let r = |f2: f64| -> f64 {
+            match self {
+                Weighting::Z => 1.0,
+                Weighting::A => {
+                    12194f64.powi(2) * f2 * f2
+                        / ((f2 + 20.6f64.powi(2))
+                            * ((f2 + 107.7f64.powi(2)) * (f2 + 737.9f64.powi(2))).sqrt()
+                            * (f2 + 12194f64.powi(2)))
+                }
+                Weighting::C => {
+                    12194f64.powi(2) * f2 / ((f2 + 20.6f64.powi(2)) * (f2 + 12194f64.powi(2)))
+                }
+            }
+        };
+        // normalized to unity at 1 kHz
+        (r(f2) / r(1e6)) as f32
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Averaging {
+    Off,
+    // Exponential averaging of power with a time constant in seconds
+    Exponential(f32),
+    PeakHold,
+}
+
+pub struct Spectrum {
+    mode: AnalysisMode,
+    welch: WelchParams,
+    multitaper: MultitaperParams,
+    zoom: ZoomParams,
+    multires: MultiResParams,
+    cwt: CwtParams,
+    unit: DensityUnit,
+    // Voltage of a full scale sample, calibrates the V²/Hz readout
+    full_scale_volts: f32,
+    weighting: Weighting,
+    averaging: Averaging,
+    // Running average, in power
+    average: Option<Frame>,
+}
 
 impl Default for Spectrum {
     fn default() -> Self {
-        Self {}
+        Self {
+            mode: AnalysisMode::Spectrum,
+            welch: WelchParams::default(),
+            multitaper: MultitaperParams::default(),
+            zoom: ZoomParams::default(),
+            multires: MultiResParams::default(),
+            cwt: CwtParams::default(),
+            unit: DensityUnit::DbfsPerRtHz,
+            full_scale_volts: 1.0,
+            weighting: Weighting::Z,
+            averaging: Averaging::Off,
+            average: None,
+        }
+    }
+}
+
+fn db(power: f32) -> f32 {
+    10.0 * power.max(1e-30).log10()
+}
+
+// Densities are in full scale peak squared per Hz. dBFS follows AES17, where 0 dBFS
+// is the power of a full scale sine, so a density reads 3 dB higher than its power.
+fn dbfs_density(density: f32) -> f32 {
+    db(density / 0.5)
+}
+
+// Power of a bin, amplitude frames are squared and density frames already are power.
+pub fn to_power(scale: Scale, bin: f32) -> f32 {
+    match scale {
+        Scale::Amplitude => bin * bin,
+        Scale::Density => bin,
+    }
+}
+
+pub fn from_power(scale: Scale, power: f32) -> f32 {
+    match scale {
+        Scale::Amplitude => power.sqrt(),
+        Scale::Density => power,
     }
 }
 
 impl Spectrum {
-    // fn options_ui(&mut self, ui: &mut Ui) {
-    //     let Self { proportional, .. } = self;
+    // Returns the new analysis mode when the user changed it.
+    pub fn options_ui(&mut self, ui: &mut egui::Ui, sample_rate: usize) -> Option<AnalysisMode> {
+        let Self {
+            mode,
+            welch,
+            multitaper,
+            zoom,
+            multires,
+            cwt,
+            unit,
+            full_scale_volts,
+            weighting,
+            averaging,
+            ..
+        } = self;
+        let old_mode = *mode;
 
-    //     ui.horizontal(|ui| {
-    //         ui.checkbox(proportional, "proportional data axes");
-    //     });
-    // }
+        let mut kind = match mode {
+            AnalysisMode::Spectrum => ModeKind::Spectrum,
+            AnalysisMode::Psd(_) => ModeKind::Psd,
+            AnalysisMode::Multitaper(_) => ModeKind::Multitaper,
+            AnalysisMode::Zoom(_) => ModeKind::Zoom,
+            AnalysisMode::MultiRes(_) => ModeKind::MultiRes,
+            AnalysisMode::Cwt(_) => ModeKind::Cwt,
+        };
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut kind, ModeKind::Spectrum, "spectrum");
+            ui.radio_value(&mut kind, ModeKind::Psd, "PSD (Welch)");
+            ui.radio_value(&mut kind, ModeKind::Multitaper, "PSD (multitaper)");
+            ui.radio_value(&mut kind, ModeKind::Zoom, "zoom");
+            ui.radio_value(&mut kind, ModeKind::MultiRes, "multi-resolution");
+            ui.radio_value(&mut kind, ModeKind::Cwt, "wavelet");
+        });
 
-    fn sin(&self, data: &Vec<f32>) -> Curve {
-        Curve::from_ys_f32(&data)
-            .color(egui::Color32::from_rgb(200, 100, 100))
-            .name("0.5 * sin(2x) * sin(t)")
+        if kind == ModeKind::Cwt {
+            let nyquist = sample_rate as f32 / 2.0;
+            ui.add(egui::Slider::new(&mut cwt.voices, 1..=48).text("scales per octave"));
+            // long wavelets at the lowest scale need more history than is kept
+            let max_cycles = cwt.max_cycles(sample_rate).max(2.0).min(20.0);
+            cwt.cycles = cwt.cycles.min(max_cycles);
+            ui.add(egui::Slider::new(&mut cwt.cycles, 2.0..=max_cycles).text("cycles"));
+            ui.add(
+                egui::DragValue::new(&mut cwt.min_hz)
+                    .speed(1.0)
+                    .clamp_range(1.0..=nyquist)
+                    .prefix("from ")
+                    .suffix(" Hz"),
+            );
+            ui.add(
+                egui::DragValue::new(&mut cwt.max_hz)
+                    .speed(10.0)
+                    .clamp_range(cwt.min_hz..=nyquist)
+                    .prefix("to ")
+                    .suffix(" Hz"),
+            );
+            ui.label(format!(
+                "{} scales, {:.1} ms at 100 Hz, {:.2} ms at 10 kHz, {:.2} s behind",
+                cwt.scales(sample_rate),
+                cwt.time_resolution(100.0) * 1000.0,
+                cwt.time_resolution(10000.0) * 1000.0,
+                cwt.delay(sample_rate)
+            ));
+        }
+
+        if kind == ModeKind::MultiRes {
+            for t in 0..TIERS {
+                let size = &mut multires.sizes[t];
+                egui::ComboBox::from_label(format!("tier {} FFT", t + 1))
+                    .selected_text(size.to_string())
+                    .show_ui(ui, |ui| {
+                        for s in MULTIRES_SIZES.iter() {
+                            ui.selectable_value(size, *s, s.to_string());
+                        }
+                    });
+                if t < TIERS - 1 {
+                    ui.add(
+                        egui::DragValue::new(&mut multires.crossovers[t])
+                            .speed(1.0)
+                            .clamp_range(20.0..=sample_rate as f32 / 2.0)
+                            .prefix("up to ")
+                            .suffix(" Hz"),
+                    );
+                }
+            }
+            ui.label("the longest FFT always covers the lowest band");
+        }
+
+        if kind == ModeKind::Zoom {
+            let nyquist = sample_rate as f32 / 2.0;
+            ui.add(
+                egui::DragValue::new(&mut zoom.center_hz)
+                    .speed(0.1)
+                    .clamp_range(0.0..=nyquist)
+                    .prefix("center ")
+                    .suffix(" Hz"),
+            );
+            egui::ComboBox::from_label("span")
+                .selected_text(format!("{} Hz", zoom.span_hz))
+                .show_ui(ui, |ui| {
+                    for span in ZOOM_SPANS.iter() {
+                        ui.selectable_value(&mut zoom.span_hz, *span, format!("{} Hz", span));
+                    }
+                });
+            egui::ComboBox::from_label("zoom FFT")
+                .selected_text(zoom.size.to_string())
+                .show_ui(ui, |ui| {
+                    for size in ZOOM_SIZES.iter() {
+                        ui.selectable_value(&mut zoom.size, *size, size.to_string());
+                    }
+                });
+            ui.label(format!(
+                "bin {:.3} Hz, decimation {}, {:.1} s per transform",
+                zoom.resolution(sample_rate),
+                zoom.decimation(sample_rate),
+                zoom.duration(sample_rate)
+            ));
+        }
+
+        if kind == ModeKind::Psd {
+            egui::ComboBox::from_label("segment")
+                .selected_text(welch.segment.to_string())
+                .show_ui(ui, |ui| {
+                    for size in WELCH_SEGMENTS.iter() {
+                        ui.selectable_value(&mut welch.segment, *size, size.to_string());
+                    }
+                });
+            ui.add(egui::Slider::new(&mut welch.averages, 1..=256).text("averages"));
+            ui.label(format!(
+                "bin {:.2} Hz, ENBW {:.2} Hz, 50% overlap",
+                sample_rate as f32 / welch.segment as f32,
+                enbw(&hann(welch.segment), sample_rate)
+            ));
+        }
+
+        if kind == ModeKind::Multitaper {
+            egui::ComboBox::from_label("segment")
+                .selected_text(multitaper.segment.to_string())
+                .show_ui(ui, |ui| {
+                    for size in MULTITAPER_SEGMENTS.iter() {
+                        ui.selectable_value(&mut multitaper.segment, *size, size.to_string());
+                    }
+                });
+            ui.add(egui::Slider::new(&mut multitaper.bandwidth, 1.0..=10.0).text("NW"));
+            // beyond 2NW - 1 the tapers leak too much to be worth averaging
+            let max_tapers = ((2.0 * multitaper.bandwidth) as usize - 1).max(1);
+            multitaper.tapers = multitaper.tapers.min(max_tapers);
+            ui.add(egui::Slider::new(&mut multitaper.tapers, 1..=max_tapers).text("tapers"));
+            ui.label(format!(
+                "resolution ±{:.1} Hz",
+                multitaper.half_bandwidth(sample_rate)
+            ));
+        }
+
+        if kind == ModeKind::Psd || kind == ModeKind::Multitaper {
+            ui.horizontal(|ui| {
+                ui.radio_value(unit, DensityUnit::DbfsPerRtHz, "dBFS/√Hz");
+                ui.radio_value(unit, DensityUnit::VoltsSqPerHz, "V²/Hz");
+            });
+            ui.add(
+                egui::DragValue::new(full_scale_volts)
+                    .speed(0.01)
+                    .clamp_range(0.001..=100.0)
+                    .suffix(" V full scale"),
+            );
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("weighting");
+            ui.radio_value(weighting, Weighting::Z, "Z");
+            ui.radio_value(weighting, Weighting::A, "A");
+            ui.radio_value(weighting, Weighting::C, "C");
+        });
+
+        egui::ComboBox::from_label("averaging")
+            .selected_text(match averaging {
+                Averaging::Off => "off".to_owned(),
+                Averaging::Exponential(tau) => format!("{} s", tau),
+                Averaging::PeakHold => "peak hold".to_owned(),
+            })
+            .show_ui(ui, |ui| {
+                ui.selectable_value(averaging, Averaging::Off, "off");
+                ui.selectable_value(averaging, Averaging::Exponential(0.125), "fast (0.125 s)");
+                ui.selectable_value(averaging, Averaging::Exponential(1.0), "slow (1 s)");
+                ui.selectable_value(averaging, Averaging::Exponential(10.0), "10 s");
+                ui.selectable_value(averaging, Averaging::PeakHold, "peak hold");
+            });
+        if ui.button("reset average").clicked() {
+            self.average = None;
+        }
+
+        *mode = match kind {
+            ModeKind::Spectrum => AnalysisMode::Spectrum,
+            ModeKind::Psd => AnalysisMode::Psd(*welch),
+            ModeKind::Multitaper => AnalysisMode::Multitaper(*multitaper),
+            ModeKind::Zoom => AnalysisMode::Zoom(*zoom),
+            ModeKind::MultiRes => AnalysisMode::MultiRes(*multires),
+            ModeKind::Cwt => AnalysisMode::Cwt(*cwt),
+        };
+
+        if *mode != old_mode {
+            Some(*mode)
+        } else {
+            None
+        }
     }
 
-    pub fn ui(&mut self, ui: &mut egui::Ui, height: f32, data: &Vec<f32>) {
-        // self.options_ui(ui);
+    // Applies the weighting and averaging choices to a new frame. Every frame coming
+    // out of the analyzer goes through here, the result is what all views display.
+    pub fn process(&mut self, frame: Frame) -> Frame {
+        let scale = frame.scale;
+        let weighting = self.weighting;
+        let power: Vec<f32> = frame
+            .bins
+            .iter()
+            .enumerate()
+            .map(|(i, b)| {
+                let g = weighting.gain(frame.axis.hz(i));
+                to_power(scale, *b) * g * g
+            })
+            .collect();
+
+        let compatible = match &self.average {
+            Some(avg) => {
+                avg.axis == frame.axis && avg.scale == scale && avg.bins.len() == power.len()
+            }
+            None => false,
+        };
+        let average = match (self.averaging, compatible) {
+            (Averaging::Off, _) | (_, false) => power,
+            (Averaging::Exponential(tau), true) => {
+                let alpha = 1.0 - (-frame.period / tau).exp();
+                let prev = &self.average.as_ref().unwrap().bins;
+                prev.iter()
+                    .zip(power.iter())
+                    .map(|(a, p)| a + alpha * (p - a))
+                    .collect()
+            }
+            (Averaging::PeakHold, true) => {
+                let prev = &self.average.as_ref().unwrap().bins;
+                prev.iter()
+                    .zip(power.iter())
+                    .map(|(a, p)| a.max(*p))
+                    .collect()
+            }
+        };
+
+        let out = Frame {
+            bins: average.iter().map(|p| from_power(scale, *p)).collect(),
+            ..frame
+        };
+        self.average = Some(Frame {
+            bins: average,
+            ..out.clone()
+        });
+        out
+    }
 
-        let plot = Plot::new("Demo Plot")
-            .curve(self.sin(data))
+    fn value(&self, frame: &Frame, bin: f32) -> f32 {
+        match (frame.scale, self.unit) {
+            (Scale::Amplitude, _) => bin,
+            (Scale::Density, DensityUnit::DbfsPerRtHz) => dbfs_density(bin),
+            (Scale::Density, DensityUnit::VoltsSqPerHz) => {
+                bin * self.full_scale_volts * self.full_scale_volts
+            }
+        }
+    }
+
+    // Log axes are plotted against log10 Hz so the bins spread out evenly.
+    fn curve(&self, frame: &Frame, log: bool, color: egui::Color32) -> Curve {
+        let values = frame
+            .bins
+            .iter()
+            .enumerate()
+            .map(|(i, b)| {
+                let hz = frame.axis.hz(i);
+                let x = if log { hz.log10() } else { hz };
+                Value::new(x as f64, self.value(frame, *b) as f64)
+            })
+            .collect();
+        Curve::from_values(values)
+            .color(color)
+            .name(if log { "log10 Hz" } else { "Hz" })
+    }
+
+    // The overlay in the scale of the frame, at the level that best matches the frame
+    // on average in dB across the range both cover.
+    fn fit(frame: &Frame, overlay: &Frame) -> Frame {
+        let (min_hz, max_hz) = frame.range();
+        let last = frame.bins.len() - 1;
+        let (sum, count) = overlay
+            .bins
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| {
+                let hz = overlay.axis.hz(*i);
+                hz >= min_hz && hz <= max_hz
+            })
+            .fold((0.0, 0), |(sum, count), (i, b)| {
+                let bin = (frame.axis.bin(overlay.axis.hz(i)).round() as usize).min(last);
+                let p = to_power(frame.scale, frame.bins[bin]).max(1e-30);
+                (sum + (p / (b * b).max(1e-30)).ln(), count + 1)
+            });
+        let gain = if count > 0 {
+            (sum / count as f32).exp()
+        } else {
+            1.0
+        };
+        Frame {
+            bins: overlay
+                .bins
+                .iter()
+                .map(|b| from_power(frame.scale, b * b * gain))
+                .collect(),
+            scale: frame.scale,
+            ..overlay.clone()
+        }
+    }
+
+    // One spectrograph row of len values in 0..1, spanning the frequency range of the
+    // frame on its own axis. That is 0 to nyquist except in zoom and wavelet mode.
+    pub fn spectrograph_row(&self, frame: &Frame, len: usize) -> Vec<f32> {
+        let (min_hz, max_hz) = frame.range();
+        let row = frame.resample(len, min_hz, max_hz);
+        match frame.scale {
+            Scale::Amplitude => row,
+            Scale::Density => row
+                .iter()
+                .map(|p| {
+                    ((dbfs_density(*p) - DENSITY_MIN_DB) / (DENSITY_MAX_DB - DENSITY_MIN_DB))
+                        .clamp(0.0, 1.0)
+                })
+                .collect(),
+        }
+    }
+
+    // The overlay is a smooth envelope drawn over the frame, see fit.
+    pub fn ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        height: f32,
+        frame: Option<&Frame>,
+        overlay: Option<&Frame>,
+    ) {
+        let frame = match frame {
+            Some(f) => f,
+            None => {
+                ui.add(Plot::new("Demo Plot").height(height));
+                return;
+            }
+        };
+
+        let log = frame.axis.is_log();
+        let mut plot = Plot::new("Demo Plot")
+            .curve(self.curve(frame, log, egui::Color32::from_rgb(200, 100, 100)))
             .allow_drag(false)
-            .include_y(1.0)
-            .include_y(0.0)
             .height(height);
+        if let Some(overlay) = overlay {
+            let fitted = Spectrum::fit(frame, overlay);
+            plot = plot.curve(
+                self.curve(&fitted, log, egui::Color32::from_rgb(220, 200, 100))
+                    .name("envelope"),
+            );
+        }
+        plot = match (frame.scale, self.unit) {
+            (Scale::Amplitude, _) => plot.include_y(1.0).include_y(0.0),
+            (Scale::Density, DensityUnit::DbfsPerRtHz) => {
+                plot.include_y(DENSITY_MIN_DB).include_y(DENSITY_MAX_DB)
+            }
+            (Scale::Density, DensityUnit::VoltsSqPerHz) => plot.include_y(0.0),
+        };
+
+        if frame.scale == Scale::Density {
+            // The median is a fair noise floor estimate as long as tones are sparse
+            let mut sorted = frame.bins[1..].to_vec();
+            sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            let floor = sorted[sorted.len() / 2];
+            let volts = floor.sqrt() * self.full_scale_volts;
+            ui.label(format!(
+                "noise floor {:.1} dBFS/√Hz, {:.3e} V²/Hz, {:.1} nV/√Hz",
+                dbfs_density(floor),
+                volts * volts,
+                volts * 1e9
+            ));
+        }
+
         ui.add(plot);
     }
 }
@@ -0,0 +1,168 @@
+use super::common::*;
+use super::psd::hann;
+use num_complex::Complex32;
+use rustfft::{Fft, FftPlanner};
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+// Taps of the decimation filter per unit of decimation
+const TAPS_PER_DECIMATION: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ZoomParams {
+    pub center_hz: f32,
+    pub span_hz: f32,
+    // FFT size after decimation
+    pub size: usize,
+}
+
+impl Default for ZoomParams {
+    fn default() -> Self {
+        ZoomParams {
+            center_hz: 50.0,
+            span_hz: 20.0,
+            size: 512,
+        }
+    }
+}
+
+impl ZoomParams {
+    // Decimation factor. The decimated rate is at least twice the span so the
+    // filter has room to roll off outside the displayed band.
+    pub fn decimation(&self, sample_rate: usize) -> usize {
+        ((sample_rate as f32 / (2.0 * self.span_hz)) as usize).max(1)
+    }
+
+    pub fn resolution(&self, sample_rate: usize) -> f32 {
+        sample_rate as f32 / (self.decimation(sample_rate) * self.size) as f32
+    }
+
+    // Seconds of input covered by one transform.
+    pub fn duration(&self, sample_rate: usize) -> f32 {
+        (self.decimation(sample_rate) * self.size) as f32 / sample_rate as f32
+    }
+}
+
+// Windowed sinc low pass, Blackman window, unity gain at DC.
+pub fn low_pass(taps: usize, cutoff: f64) -> Vec<f32> {
+    let m = (taps - 1) as f64;
+    let kernel: Vec<f64> = (0..taps)
+        .map(|i| {
+            let x = i as f64 - m / 2.0;
+            let sinc = if x == 0.0 {
+                2.0 * cutoff
+            } else {
+                (2.0 * PI * cutoff * x).sin() / (PI * x)
+            };
+            let w = 0.42 - 0.5 * (2.0 * PI * i as f64 / m).cos()
+                + 0.08 * (4.0 * PI * i as f64 / m).cos();
+            sinc * w
+        })
+        .collect();
+    let sum: f64 = kernel.iter().sum();
+    kernel.iter().map(|k| (k / sum) as f32).collect()
+}
+
+// Zoom FFT: the input is shifted down by the center frequency, low pass filtered and
+// decimated, then transformed with a complex FFT. This gives a fine resolution over
+// a narrow band at a fraction of the cost of one huge transform.
+pub struct ZoomFft {
+    params: ZoomParams,
+    sample_rate: usize,
+    decimation: usize,
+    kernel: Vec<f32>,
+    phase: f64,
+    phase_step: f64,
+    mixed: Vec<Complex32>,
+    decimated: Vec<Complex32>,
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    window_sum: f32,
+    buf: Vec<Complex32>,
+}
+
+impl ZoomFft {
+    pub fn new(params: ZoomParams, sample_rate: usize) -> ZoomFft {
+        let decimation = params.decimation(sample_rate);
+        let kernel = low_pass(
+            TAPS_PER_DECIMATION * decimation + 1,
+            0.5 / decimation as f64,
+        );
+        let mut planner = FftPlanner::<f32>::new();
+        let window = hann(params.size);
+
+        ZoomFft {
+            params,
+            sample_rate,
+            decimation,
+            kernel,
+            phase: 0.0,
+            phase_step: 2.0 * PI * params.center_hz as f64 / sample_rate as f64,
+            mixed: vec![],
+            decimated: Vec::with_capacity(params.size * 2),
+            fft: planner.plan_fft_forward(params.size),
+            window_sum: window.iter().sum(),
+            window,
+            buf: vec![Complex32::new(0.0, 0.0); params.size],
+        }
+    }
+
+    pub fn push(&mut self, samples: &[f32]) -> Vec<Frame> {
+        for x in samples {
+            let lo = Complex32::new(self.phase.cos() as f32, -self.phase.sin() as f32);
+            self.mixed.push(lo * x);
+            self.phase = (self.phase + self.phase_step) % (2.0 * PI);
+        }
+
+        // only every decimation-th filter output is computed
+        let taps = self.kernel.len();
+        let mut start = 0;
+        while self.mixed.len() - start >= taps {
+            let acc = self.mixed[start..start + taps]
+                .iter()
+                .zip(self.kernel.iter())
+                .fold(Complex32::new(0.0, 0.0), |acc, (x, k)| acc + x * k);
+            self.decimated.push(acc);
+            start += self.decimation;
+        }
+        self.mixed.drain(0..start);
+
+        // frames overlap by three quarters
+        let size = self.params.size;
+        let hop = (size / 4).max(1);
+        let mut frames = vec![];
+        while self.decimated.len() >= size {
+            for i in 0..size {
+                self.buf[i] = self.decimated[i] * self.window[i];
+            }
+            self.fft.process(&mut self.buf);
+            frames.push(self.frame());
+            self.decimated.drain(0..hop);
+        }
+        frames
+    }
+
+    fn frame(&self) -> Frame {
+        let size = self.params.size;
+        let step = self.params.resolution(self.sample_rate);
+        let half = ((self.params.span_hz / 2.0 / step) as usize).min(size / 2 - 1);
+
+        // bins -half..=half around the center, negative frequencies sit at the top
+        let bins = (0..2 * half + 1)
+            .map(|i| {
+                let k = (i + size - half) % size;
+                self.buf[k].norm() / self.window_sum
+            })
+            .collect();
+
+        Frame {
+            bins,
+            axis: FreqAxis::Linear {
+                start: self.params.center_hz - half as f32 * step,
+                step,
+            },
+            scale: Scale::Amplitude,
+            period: (size / 4 * self.decimation) as f32 / self.sample_rate as f32,
+        }
+    }
+}
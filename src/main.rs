@@ -8,13 +8,17 @@ use anyhow::Result;
 use crossbeam_channel;
 use glium::glutin;
 use jack;
+use std::sync::atomic::AtomicU64;
 use std::sync::{Arc, RwLock};
 use std::thread;
 use std::time::Duration;
 mod lib;
 use lib::app::{App, State};
 use lib::common::*;
-use lib::controllers::{FFTProc, PlaybackSystem, PortConnector, SineGen};
+use lib::controllers::{port_names, FFTProc, FilePlayer, Patchbay, PortConnector, SignalGen};
+use lib::generator::{self, GeneratorView};
+use lib::patchbay::{parse_rules, ConnectRule, Direction};
+use lib::player::PlayerView;
 use lib::spectrograph::Spectrograph;
 
 fn create_display(
@@ -37,50 +41,160 @@ fn create_display(
     glium::Display::new(window_builder, context_builder, &event_loop).unwrap()
 }
 
+const USAGE: &str =
+    "usage: spectrak [--channels N] [--connect-in REGEX].. [--connect-out REGEX].. [--config FILE]";
+
+struct Options {
+    channels: usize,
+    rules: Vec<ConnectRule>,
+}
+
+// Reads the command line. Connect rules come from --connect-in and --connect-out,
+// and from a config file with one `in REGEX` or `out REGEX` per line. Without any
+// rules nothing is connected beyond the analyzer, so nothing plays until asked to.
+fn parse_options() -> Result<Options> {
+    let mut args = std::env::args().skip(1);
+    let mut options = Options {
+        channels: DEFAULT_CHANNELS,
+        rules: vec![],
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| anyhow!("{} needs a value", arg));
+        match arg.as_str() {
+            "--channels" => options.channels = value()?.parse()?,
+            "--connect-in" => options.rules.push(ConnectRule {
+                direction: Direction::In,
+                pattern: value()?,
+            }),
+            "--connect-out" => options.rules.push(ConnectRule {
+                direction: Direction::Out,
+                pattern: value()?,
+            }),
+            "--config" => {
+                let text = std::fs::read_to_string(value()?)?;
+                options.rules.extend(parse_rules(&text)?);
+            }
+            _ => bail!("unknown argument {}, {}", arg, USAGE),
+        }
+    }
+    if options.channels == 0 || options.channels > MAX_CHANNELS {
+        bail!("--channels must be 1 to {}", MAX_CHANNELS);
+    }
+    Ok(options)
+}
+
 fn main() -> Result<()> {
+    let Options { channels, rules } = parse_options()?;
     let fft_size = FFT_MAX_SIZE;
     let (tx, rx) = crossbeam_channel::unbounded();
+    // Bounded so that neither end allocates, the receiving end is the Jack callback
+    let (gen_tx, gen_rx) = crossbeam_channel::bounded(GEN_COMMANDS);
+    let (midi_tx, midi_rx) = crossbeam_channel::bounded(GEN_COMMANDS);
+    let (player_tx, player_rx) = crossbeam_channel::bounded(GEN_COMMANDS);
+    let (analyzer_tx, analyzer_rx) = crossbeam_channel::unbounded();
+    let (patch_tx, patch_rx) = crossbeam_channel::unbounded();
+    // xruns and the buffer size and sample rate Jack currently runs at
+    let status = Arc::new(JackStatus::default());
+
+    let sync = Arc::new(AtomicU64::new(NO_SYNC));
 
-    // let sine_gen = SineGen::new(
-    //     "sine_gen",
-    //     jack::AudioOut::default(),
-    //     jack::AudioOut::default(),
-    //     220.0,
-    //     440.0,
-    // )?;
+    let sig_gen = SignalGen::new(
+        "sig_gen",
+        jack::AudioOut::default(),
+        &generator::default_channels(channels),
+        gen_rx,
+        sync.clone(),
+        midi_tx,
+        status.clone(),
+    )?;
 
-    // let fft_proc = FFTProc::new(
-    //     "fft_proc",
-    //     jack::AudioIn::default(),
-    //     jack::AudioIn::default(),
-    //     fft_size,
-    //     tx,
-    // )?;
+    let playhead = Arc::new(AtomicU64::new(0));
+    let file_player = FilePlayer::new(
+        "file_player",
+        jack::AudioOut::default(),
+        channels,
+        player_rx,
+        playhead.clone(),
+        status.clone(),
+    )?;
 
-    // thread::sleep(Duration::from_secs(1));
+    let fft_proc = FFTProc::new(
+        "fft_proc",
+        jack::AudioIn::default(),
+        channels,
+        fft_size,
+        tx.clone(),
+        analyzer_rx,
+        sync,
+        status.clone(),
+    )?;
+    let sample_rate = fft_proc.sample_rate;
 
-    // sine_gen.connect_to(&PlaybackSystem::new())?;
-    // sine_gen.connect_to(&fft_proc)?;
+    thread::sleep(Duration::from_secs(1));
+
+    sig_gen.connect_to(&fft_proc)?;
+    file_player.connect_to(&fft_proc)?;
+
+    let _patchbay = Patchbay::new(
+        "patchbay",
+        rules,
+        port_names(&fft_proc),
+        vec![port_names(&sig_gen), port_names(&file_player)],
+        tx,
+        patch_rx,
+    )?;
 
     let event_loop = glutin::event_loop::EventLoop::with_user_event();
     let display = create_display(&&event_loop);
 
     let mut egui = egui_glium::EguiGlium::new(&display);
 
-    let spectrograph = Spectrograph::new(
-        &display,
-        600,
-        (0.7 * APP_HEIGHT) as u32,
-        FFT_MAX_BUFF_SIZE as u32,
-        (0.7 * APP_HEIGHT) as u32,
-    );
+    let new_spectrograph = || {
+        Spectrograph::new(
+            &display,
+            600,
+            (0.7 * APP_HEIGHT) as u32,
+            FFT_MAX_BUFF_SIZE as u32,
+            (0.7 * APP_HEIGHT) as u32,
+        )
+    };
+    let spectrograph = new_spectrograph();
 
     let mut app = App {
         label: "spectrak".to_owned(),
         value: 2.17,
         plot: Default::default(),
+        rta: Default::default(),
+        lpc: Default::default(),
+        shown: None,
         last_head: 0,
         spectrograph,
+        hpss: Default::default(),
+        harmonic: new_spectrograph(),
+        percussive: new_spectrograph(),
+        impulse: Default::default(),
+        phase: Default::default(),
+        scope: Default::default(),
+        gonio: Default::default(),
+        cepstrum: Default::default(),
+        tones: Default::default(),
+        generator: GeneratorView::new(channels),
+        player: PlayerView::new(playhead),
+        hilbert: Default::default(),
+        imd: Default::default(),
+        patchbay: Default::default(),
+        raw_wanted: false,
+        sample_rate,
+        channels,
+        gen_tx,
+        midi_rx,
+        player_tx,
+        gen_queue: Default::default(),
+        player_queue: Default::default(),
+        pending_capture: None,
+        patch_tx,
+        analyzer_tx,
+        status,
     };
     let state = Arc::new(RwLock::new(State::new()));
 